@@ -48,14 +48,20 @@ client.on('messageCreate', message => {
     let text = parts.slice(1).join(' ');
     let cmd = parts[0].substring(1);
 
-    if(['np', 'skip', 'prev', 'next'].indexOf(cmd) > -1){
+    let requester = `?requester=${encodeURIComponent(message.author.username)}&via=Discord`;
+
+    if(['np', 'prev', 'next'].indexOf(cmd) > -1){
         axios.get(`${apiBase}/${cmd}`).then(response => {
             message.channel.send(makeSpotifyText(response.data));
         });
+    } else if(cmd == 'skip'){
+        axios.get(`${apiBase}/skip${requester}`).then(response => {
+            message.channel.send(makeSpotifyText(response.data));
+        });
     } else if(['play', 'queue'].indexOf(cmd) > -1 && text){
         axios.get(`${apiBase}/search/track/1?q=${text}`).then(search => {
             if(search && search.data && search.data.length > 0){
-                axios.get(`${apiBase}/${parts[0].substring(1)}/${search.data[0].id}`).then(response => {
+                axios.get(`${apiBase}/${parts[0].substring(1)}/${search.data[0].id}${requester}`).then(response => {
                     message.channel.send(makeSpotifyText(response.data));
                 });
             }
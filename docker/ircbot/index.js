@@ -34,14 +34,14 @@ bot.on('message', function(event) {
 
     if (event.message.match(/^!(queue|play) /)) {
         spotifyApi('/search/track/1?q='+text, event, data => {
-            spotifyApi('/'+cmd+'/'+data[0].id, event, data => {
+            spotifyApi('/'+cmd+'/'+data[0].id+requester(event), event, data => {
                 return _nowPlaying(data, event);
             });
         });
     }
 
     if (event.message.match(/^!skip/)) {
-        spotifyApi('/skip', event, data => {
+        spotifyApi('/skip'+requester(event), event, data => {
             return _nowPlaying(data, event);
         });
     }
@@ -81,6 +81,10 @@ bot.on('message', function(event) {
 
 });
 
+function requester(event){
+    return '?requester='+encodeURIComponent(event.nick)+'&via=IRC';
+}
+
 function _nowPlaying(data, event){
     getListenerNumber(event, listeners => {
         if(data)
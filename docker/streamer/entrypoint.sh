@@ -10,9 +10,6 @@ echo "Starting liquidsoap ..."
 liquidsoap /etc/liquidsoap/pulse.liq --daemon
 sleep 2
 
-echo "Starting keepalive ..."
-/keepalive.sh &
-
 echo "Starting spotifm ..."
 
-spotifm /etc/spotifm.json
\ No newline at end of file
+spotifm --config /etc/spotifm.json
\ No newline at end of file
@@ -1,56 +1,318 @@
-use std::process::Command;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use chrono::{Local, Timelike};
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+use serde_json::Value;
+use crate::audio;
 use crate::db::SpotifyTrack;
-use crate::config::{SpotifmConfig, SpotifmEspeakCfg, SpotifmElevenLabsCfg};
+use crate::config::{BumperSource, SpotifmConfig, SpotifmTalkoverCfg};
+use crate::output::StationOutput;
+use crate::schedule::Scheduler;
+use crate::language;
+use crate::template;
+use crate::tts::{self, TtsEngine};
 
-pub fn announcements(_config: Arc<Mutex<SpotifmConfig>>, track: &SpotifyTrack, tracks_played: usize) {
-    let mut config = _config.lock().unwrap();
+// listener actions with their own announcement
+pub enum AnnounceEvent {
+    Request,
+    Skip,
+    Play,
+}
+
+// an announcement decided on while holding the config lock, rendered after releasing it
+enum Pending {
+    Speech(String, Box<dyn TtsEngine>),
+    File(PathBuf),
+}
+
+// queues the scheduled announcements that are due, followed by the prepared `clips`
+pub fn announcements(
+    _config: Arc<Mutex<SpotifmConfig>>,
+    track: &SpotifyTrack,
+    prev: Option<&SpotifyTrack>,
+    clips: Vec<Vec<f64>>,
+    scheduler: &mut Scheduler,
+    output: &StationOutput,
+) {
+    let (due, talkover, listeners_url) = {
+        let config = _config.lock().unwrap();
+        (
+            scheduler.due(&config.announce.schedule),
+            config.announce.talkover.clone(),
+            config.announce.listeners_url.clone(),
+        )
+    };
+
+    // scheduled announcements depend on the clock so they aren't pre-rendered, they use the bumper voice
+    if due.len() > 0 {
+        let vars = context(track, prev, listeners_url.and_then(|url| listeners(url.as_str())));
+        let engine = {
+            let config = _config.lock().unwrap();
+            tts::engine(config.announce.bumper.engine, &config.announce.bumper.espeak, &config)
+        };
+        for scheduled in due {
+            match render(template::render(scheduled.as_str(), &vars).as_str(), engine.as_ref()) {
+                Err(_) => {}
+                Ok(samples) => queue(samples, output, &talkover),
+            }
+        }
+    }
+
+    for clip in clips {
+        queue(clip, output, &talkover);
+    }
+}
+
+// renders the request and song announcement for `track` in the background
+pub fn prerender(config: Arc<Mutex<SpotifmConfig>>, track: SpotifyTrack, prev: SpotifyTrack) -> (u64, JoinHandle<Vec<Vec<f64>>>) {
+    let rid = track.rid;
+    let handle = thread::spawn(move || prepare(config, &track, Some(&prev)));
+    return (rid, handle);
+}
+
+// picks and renders the bumper once `track` actually starts, so renders that are thrown away
+// don't move the tag and jingle rotation along
+pub fn bumper(
+    _config: Arc<Mutex<SpotifmConfig>>,
+    track: &SpotifyTrack,
+    prev: Option<&SpotifyTrack>,
+    tracks_played: usize,
+) -> Option<Vec<f64>> {
+    let (pending, listeners_url) = {
+        let mut config = _config.lock().unwrap();
+
+        if !config.announce.bumper.enable
+            || config.announce.bumper.freq == 0
+            || tracks_played % config.announce.bumper.freq != 0
+        {
+            return None;
+        }
+
+        let jingle = match config.announce.bumper.source {
+            BumperSource::Tags => None,
+            BumperSource::Jingles => config.announce.bumper.jingles.next(),
+            BumperSource::Both => match thread_rng().gen_bool(0.5) {
+                true => config.announce.bumper.jingles.next(),
+                false => None,
+            },
+        };
+
+        let pending = match jingle {
+            Some(path) => Pending::File(path),
+            None => {
+                let tag = config.announce.bumper.next()?;
+                Pending::Speech(tag, tts::engine(config.announce.bumper.engine, &config.announce.bumper.espeak, &config))
+            }
+        };
+
+        (pending, config.announce.listeners_url.clone())
+    };
+
+    return match pending {
+        Pending::Speech(tag, engine) => {
+            let vars = context(track, prev, listeners_url.and_then(|url| listeners(url.as_str())));
+            render(template::render(tag.as_str(), &vars).as_str(), engine.as_ref()).ok()
+        }
+        Pending::File(path) => decode_file(&path).ok(),
+    };
+}
+
+// renders the request and song announcement for `track`
+pub fn prepare(_config: Arc<Mutex<SpotifmConfig>>, track: &SpotifyTrack, prev: Option<&SpotifyTrack>) -> Vec<Vec<f64>> {
+    let listeners_url = _config.lock().unwrap().announce.listeners_url.clone();
+    let vars = context(track, prev, listeners_url.and_then(|url| listeners(url.as_str())));
+
+    let mut pending: Vec<Pending> = Vec::new();
+
+    {
+        let config = _config.lock().unwrap();
+
+        // a track played right away already got its shout-out
+        if track.requester.is_some() && !track.shouted_out {
+            match event_template(&config, AnnounceEvent::Request) {
+                None => {}
+                Some(event_template) => {
+                    let text = template::render(event_template.as_str(), &vars);
+                    let engine = song_engine(&config, track);
+                    pending.push(Pending::Speech(text, engine));
+                }
+            }
+        }
+
+        if config.announce.song.enable {
+            match config.announce.song.templates.choose(&mut thread_rng()) {
+                None => {}
+                Some(announce_template) => {
+                    let text = template::render(announce_template.as_str(), &vars);
+                    let engine = song_engine(&config, track);
+                    pending.push(Pending::Speech(text, engine));
+                }
+            }
+        }
+    }
+
+    return pending
+        .into_iter()
+        .filter_map(|x| match x {
+            Pending::Speech(text, engine) => render(text.as_str(), engine.as_ref()).ok(),
+            Pending::File(path) => decode_file(&path).ok(),
+        })
+        .collect();
+}
+
+// true if `event` is announced at all
+pub fn announces(config: &SpotifmConfig, event: AnnounceEvent) -> bool {
+    return event_template(config, event).is_some();
+}
+
+// renders an event announcement in the song voice in the background, `track` fills in the template
+pub fn event(_config: Arc<Mutex<SpotifmConfig>>, event: AnnounceEvent, track: SpotifyTrack) -> JoinHandle<Option<Vec<f64>>> {
+    return thread::spawn(move || {
+        let (text, engine) = {
+            let config = _config.lock().unwrap();
+            match event_template(&config, event) {
+                None => return None,
+                Some(event_template) => (
+                    template::render(event_template.as_str(), &context(&track, None, None)),
+                    song_engine(&config, &track),
+                ),
+            }
+        };
+
+        return render(text.as_str(), engine.as_ref()).ok();
+    });
+}
+
+// the song voice, switched to the configured voice for the title's language when there is one
+fn song_engine(config: &SpotifmConfig, track: &SpotifyTrack) -> Box<dyn TtsEngine> {
+    let song = &config.announce.song;
+
+    if config.announce.voices.enable {
+        let voices = &config.announce.voices.languages;
+        let voice = language::voice(&language::detect(track.track.as_str()), voices)
+            .or_else(|| language::voice(&language::detect(track.artists.join(" ").as_str()), voices));
 
-    if config.announce.bumper.enable {
-        if config.announce.bumper.freq > 0 && tracks_played % config.announce.bumper.freq == 0 {
-            espeak(config.announce.bumper.next(), config.announce.clone().bumper.espeak);
+        match voice {
+            None => {}
+            Some(voice) => return tts::with_voice(song.engine, &song.espeak, config, voice.as_str()),
         }
     }
 
-    if config.announce.song.enable {
-        let announce_text = format!(
-            "{} by {}",
-            track.track,
-            track.artists[0]
-        );
-        espeak(announce_text, config.announce.clone().song.espeak);
-    }
-}
-
-pub fn espeak(text: String, config: SpotifmEspeakCfg){
-    Command::new("espeak")
-        .arg("-s")
-        .arg(config.speed.to_string())
-        .arg("-a")
-        .arg(config.amplitude.to_string())
-        .arg("-p")
-        .arg(config.pitch.to_string())
-        .arg("-g")
-        .arg(config.gap.to_string())
-        .arg("-v")
-        .arg(config.voice.to_string())
-        .arg(text)
-        .output()
-        .unwrap();
-}
-
-pub fn get_elevenlabs_tts(text: &str, config: SpotifmElevenLabsCfg) {
-    Command::new("/elevenlabs.sh")
-        .arg(config.key)
-        .arg(config.voice)
-        .arg(text)
-        .output()
-        .unwrap();
-}
-
-pub fn play_elevenlabs(){
-    Command::new("mplayer")
-        .arg("/tmp/output.mp3")
-        .output()
-        .unwrap();
-}
\ No newline at end of file
+    return tts::engine(song.engine, &song.espeak, config);
+}
+
+// a random template for `event`, or none if it's disabled
+fn event_template(config: &SpotifmConfig, event: AnnounceEvent) -> Option<String> {
+    let (event_cfg, fallback) = match event {
+        AnnounceEvent::Request => (&config.announce.events.request, "requested by {requester}"),
+        AnnounceEvent::Skip => (&config.announce.events.skip, "skipping {track}"),
+        AnnounceEvent::Play => (&config.announce.events.play, "this one goes out to {requester}"),
+    };
+
+    if !event_cfg.enable {
+        return None;
+    }
+
+    return match event_cfg.templates.choose(&mut thread_rng()) {
+        None => Some(fallback.to_string()),
+        Some(event_template) => Some(event_template.clone()),
+    };
+}
+
+// the fields available to announcement templates
+pub fn context(track: &SpotifyTrack, prev: Option<&SpotifyTrack>, listeners: Option<u64>) -> HashMap<String, String> {
+    let mut vars: HashMap<String, String> = HashMap::new();
+    let now = Local::now();
+
+    vars.insert("track".to_string(), track.track.clone());
+    vars.insert("artist".to_string(), track.artists.get(0).cloned().unwrap_or_default());
+    vars.insert("artists_joined".to_string(), template::join_names(&track.artists));
+
+    if let Some(prev) = prev {
+        vars.insert("prev.track".to_string(), prev.track.clone());
+        vars.insert("prev.artist".to_string(), prev.artists.get(0).cloned().unwrap_or_default());
+        vars.insert("prev.artists_joined".to_string(), template::join_names(&prev.artists));
+    }
+
+    if let Some(requester) = &track.requester {
+        vars.insert("requester".to_string(), requester.clone());
+    }
+
+    if let Some(via) = &track.via {
+        vars.insert("via".to_string(), via.clone());
+    }
+
+    if let Some(listeners) = listeners {
+        vars.insert("listeners".to_string(), listeners.to_string());
+    }
+
+    vars.insert("time".to_string(), now.format("%-I:%M %p").to_string());
+    vars.insert("hour".to_string(), now.format("%-I").to_string());
+    vars.insert("minute".to_string(), now.format("%M").to_string());
+    vars.insert("weekday".to_string(), now.format("%A").to_string());
+    vars.insert(
+        "part_of_day".to_string(),
+        match now.hour() {
+            5..=11 => "morning",
+            12..=16 => "afternoon",
+            17..=21 => "evening",
+            _ => "night",
+        }
+        .to_string(),
+    );
+
+    return vars;
+}
+
+// current listener count from an icecast status-json.xsl url
+fn listeners(url: &str) -> Option<u64> {
+    let stats: Value = ureq::get(url)
+        .timeout(Duration::from_secs(2))
+        .call()
+        .ok()?
+        .into_json()
+        .ok()?;
+
+    return match &stats["icestats"]["source"] {
+        Value::Array(sources) => Some(sources.iter().filter_map(|x| x["listeners"].as_u64()).sum()),
+        source => source["listeners"].as_u64(),
+    };
+}
+
+fn queue(samples: Vec<f64>, output: &StationOutput, talkover: &SpotifmTalkoverCfg) {
+    if talkover.enable {
+        output.talk_over(samples, talkover);
+    } else {
+        output.queue(samples);
+    }
+}
+
+fn decode_file(path: &Path) -> Result<Vec<f64>, String> {
+    return std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(audio::decode)
+        .map_err(|err| {
+            eprintln!("Error playing {}: {}", path.display(), err);
+            err
+        });
+}
+
+// renders `text` and queues it on the station output
+pub fn say(text: String, engine: &dyn TtsEngine, output: &StationOutput) -> Result<(), String> {
+    output.queue(render(&text, engine)?);
+    return Ok(());
+}
+
+fn render(text: &str, engine: &dyn TtsEngine) -> Result<Vec<f64>, String> {
+    return engine
+        .synthesize(text)
+        .and_then(audio::decode)
+        .map_err(|err| {
+            eprintln!("Error synthesizing \"{}\": {}", text, err);
+            err
+        });
+}
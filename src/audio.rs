@@ -0,0 +1,95 @@
+use std::io::Cursor;
+use librespot::playback::{NUM_CHANNELS, SAMPLE_RATE};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+// decodes an audio file (wav, ogg, mp3, flac) to interleaved samples in the player's output format
+pub fn decode(bytes: Vec<u8>) -> Result<Vec<f64>, String> {
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+
+    let probed = symphonia::default::get_probe()
+        .format(&Hint::new(), stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|err| format!("unsupported audio: {}", err))?;
+
+    let mut format = probed.format;
+    let track = format.default_track().ok_or("no audio track found")?;
+    let track_id = track.id;
+    let mut rate = track.codec_params.sample_rate.unwrap_or(SAMPLE_RATE);
+    let mut channels = track.codec_params.channels.map(|x| x.count()).unwrap_or(1);
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|err| format!("unsupported codec: {}", err))?;
+
+    let mut samples: Vec<f32> = Vec::new();
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(_)) => break,
+            Err(err) => return Err(err.to_string()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                rate = spec.rate;
+                channels = spec.channels.count();
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+                samples.extend_from_slice(buffer.samples());
+            }
+            Err(Error::DecodeError(_)) => continue,
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+
+    return Ok(resample(&to_stereo(&samples, channels), rate));
+}
+
+fn to_stereo(samples: &[f32], channels: usize) -> Vec<f64> {
+    return match channels {
+        0 => Vec::new(),
+        1 => samples.iter().flat_map(|&x| [x as f64, x as f64]).collect(),
+        _ => samples
+            .chunks_exact(channels)
+            .flat_map(|frame| [frame[0] as f64, frame[1] as f64])
+            .collect(),
+    };
+}
+
+// linear interpolation, good enough for speech and jingles
+fn resample(samples: &[f64], rate: u32) -> Vec<f64> {
+    let channels = NUM_CHANNELS as usize;
+
+    if rate == SAMPLE_RATE || samples.len() < channels * 2 {
+        return samples.to_vec();
+    }
+
+    let frames = samples.len() / channels;
+    let step = rate as f64 / SAMPLE_RATE as f64;
+    let out_frames = (frames as f64 / step) as usize;
+    let mut out: Vec<f64> = Vec::with_capacity(out_frames * channels);
+
+    for idx in 0..out_frames {
+        let pos = idx as f64 * step;
+        let frame = (pos as usize).min(frames - 2);
+        let frac = pos - frame as f64;
+        for channel in 0..channels {
+            let a = samples[frame * channels + channel];
+            let b = samples[(frame + 1) * channels + channel];
+            out.push(a + (b - a) * frac);
+        }
+    }
+
+    return out;
+}
@@ -0,0 +1,122 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use librespot::core::authentication::Credentials;
+use librespot::core::cache::Cache;
+use librespot::core::config::SessionConfig;
+use librespot::core::keymaster;
+use librespot::core::session::Session;
+use librespot::protocol::authentication::AuthenticationType;
+use rspotify::AuthCodeSpotify;
+use std::collections::HashSet;
+use std::iter::FromIterator;
+
+use crate::config::SpotifmConfig;
+
+const CLIENT_ID: &str = "65b708073fc0480ea92a077233ca87bd";
+const SCOPES: &str =
+    "streaming,user-read-playback-state,user-modify-playback-state,user-read-currently-playing";
+
+// the credential cache, where librespot keeps the reusable credentials from the last login
+pub fn cache(config: &SpotifmConfig) -> Option<Cache> {
+    let dir = config.auth.cache_dir.as_ref()?;
+
+    return match Cache::new(Some(dir.as_str()), None, None, None) {
+        Err(err) => {
+            eprintln!("Error opening credential cache {}: {}", dir, err);
+            None
+        }
+        Ok(cache) => Some(cache),
+    };
+}
+
+// cached credentials for `user`, or for whoever logged in last if no user is set
+pub fn cached_credentials(config: &SpotifmConfig) -> Option<Credentials> {
+    let credentials = cache(config)?.credentials()?;
+
+    if !config.user.is_empty() && credentials.username != config.user {
+        return None;
+    }
+
+    return Some(credentials);
+}
+
+// the credentials to log in with, first found of:
+//   1. an access token in `auth.token`
+//   2. a stored auth blob in `auth.blob`
+//   3. the credential cache
+//   4. `user` and `pass`
+pub fn credentials(config: &SpotifmConfig) -> Result<Credentials, String> {
+    if let Some(token) = &config.auth.token {
+        return Ok(Credentials {
+            username: config.user.clone(),
+            auth_type: AuthenticationType::AUTHENTICATION_SPOTIFY_TOKEN,
+            auth_data: token.as_bytes().to_vec(),
+        });
+    }
+
+    if let Some(blob) = &config.auth.blob {
+        return Ok(Credentials {
+            username: config.user.clone(),
+            auth_type: AuthenticationType::AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS,
+            auth_data: base64::decode(blob.trim()).map_err(|err| format!("auth.blob is not base64: {}", err))?,
+        });
+    }
+
+    if let Some(credentials) = cached_credentials(config) {
+        return Ok(credentials);
+    }
+
+    if config.pass.is_empty() {
+        return Err("no password, auth blob, token or cached credentials to log in with".to_string());
+    }
+
+    return Ok(Credentials::with_password(config.user.clone(), config.pass.clone()));
+}
+
+// logs in with the credentials picked by `credentials`, falling back to the password when cached ones
+// are rejected (revoked or stale), which also refreshes the cache
+pub async fn login(config: &SpotifmConfig) -> Result<(Session, Credentials), String> {
+    let credentials = credentials(config)?;
+
+    let cached = config.auth.token.is_none()
+        && config.auth.blob.is_none()
+        && credentials.auth_type != AuthenticationType::AUTHENTICATION_USER_PASS;
+
+    return match connect(config, credentials).await {
+        Err(err) if cached && !config.pass.is_empty() => {
+            eprintln!("Login with cached credentials failed ({}), trying the password", err);
+            connect(config, Credentials::with_password(config.user.clone(), config.pass.clone())).await
+        }
+        result => result,
+    };
+}
+
+// logs in with `credentials`, the reusable credentials spotify hands back are written to the cache
+pub async fn connect(config: &SpotifmConfig, credentials: Credentials) -> Result<(Session, Credentials), String> {
+    return Session::connect(SessionConfig::default(), credentials, cache(config), true)
+        .await
+        .map_err(|err| err.to_string());
+}
+
+// a web api client authorized with a token from the session
+pub async fn web_api(session: &Session) -> Result<AuthCodeSpotify, String> {
+    return match keymaster::get_token(session, CLIENT_ID, SCOPES).await {
+        Err(_) => Err("could not get token".to_string()),
+        Ok(search_token) => {
+            let token = rspotify::Token {
+                access_token: search_token.access_token.clone(),
+                expires_in: ChronoDuration::seconds(search_token.expires_in.into()),
+                expires_at: Some(
+                    Utc::now() + ChronoDuration::seconds(search_token.expires_in.into()),
+                ),
+                refresh_token: None,
+                scopes: HashSet::from_iter(SCOPES.split(",").into_iter().map(|x| x.to_string())),
+            };
+
+            let mut spotify = rspotify::AuthCodeSpotify::from_token(token.clone());
+
+            spotify.creds.id = CLIENT_ID.to_string();
+
+            Ok(spotify)
+        }
+    };
+}
@@ -0,0 +1,208 @@
+use clap::{Parser, Subcommand};
+use librespot::core::authentication::Credentials;
+use librespot::core::session::Session;
+
+use crate::auth;
+use crate::config::{self, SpotifmConfig};
+use crate::db::{fetch_track, resolve_uri};
+
+// exit codes, clap exits with 2 on usage errors
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_CONFIG: i32 = 3;
+pub const EXIT_LOGIN: i32 = 4;
+pub const EXIT_SOURCE: i32 = 5;
+pub const EXIT_REST: i32 = 6;
+
+#[derive(Parser)]
+#[command(name = "spotifm", version, about = "stream spotify via icecast")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// the config file
+    #[arg(short, long, global = true, default_value = "/etc/spotifm.json")]
+    pub config: String,
+
+    /// address the rest api listens on
+    #[arg(long, default_value = "0.0.0.0:9090")]
+    pub rest_listen: String,
+
+    /// log filter for the rest api and librespot, e.g. `warn` or `actix_web=info`
+    #[arg(long, default_value = "warn")]
+    pub log_level: String,
+
+    /// don't start the rest api
+    #[arg(long)]
+    pub no_rest: bool,
+
+    /// log in and resolve the sources, then exit without playing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// the config file, for `spotifm <config.json>`
+    #[arg(hide = true)]
+    pub config_path: Option<String>,
+
+    /// same as the check-config command
+    #[arg(long, hide = true)]
+    pub check_config: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// validate the config file and exit
+    CheckConfig,
+    /// list the configured sources
+    ListSources,
+    /// log in and list the tracks of a spotify uri
+    Resolve { uri: String },
+    /// log in once and cache the credentials, so the password can be removed from the config
+    Login {
+        /// the spotify user, defaults to `user` from the config
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// print the version
+    Version,
+    /// config file helpers
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// rewrite a config file as json, toml or yaml, going by the file extensions
+    Convert { input: String, output: String },
+}
+
+impl Cli {
+    pub fn config_path(&self) -> String {
+        return self.config_path.clone().unwrap_or(self.config.clone());
+    }
+}
+
+pub fn check_config(path: &str) -> i32 {
+    return match SpotifmConfig::read(path) {
+        Err(err) => {
+            eprintln!("{}", err);
+            EXIT_CONFIG
+        }
+        Ok(_) => {
+            eprintln!("{} is valid", path);
+            EXIT_OK
+        }
+    };
+}
+
+pub fn convert(input: &str, output: &str) -> i32 {
+    return match config::convert(input, output) {
+        Err(err) => {
+            eprintln!("{}", err);
+            EXIT_CONFIG
+        }
+        Ok(_) => {
+            eprintln!("Converted {} to {}", input, output);
+            EXIT_OK
+        }
+    };
+}
+
+// logs in with a password and leaves reusable credentials in the cache
+pub async fn login(path: &str, user: Option<String>) -> i32 {
+    let mut config = match SpotifmConfig::read_unchecked(path) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return EXIT_CONFIG;
+        }
+        Ok(config) => config,
+    };
+
+    if config.auth.cache_dir.is_none() {
+        eprintln!("auth.cache_dir is not set, there is nowhere to cache the credentials");
+        return EXIT_CONFIG;
+    }
+
+    if let Some(user) = user {
+        config.user = user;
+    }
+
+    if config.user.is_empty() {
+        eprintln!("no user, set `user` in the config or pass --user");
+        return EXIT_CONFIG;
+    }
+
+    if config.pass.is_empty() {
+        config.pass = match rpassword::prompt_password(format!("Password for {}: ", config.user)) {
+            Err(err) => {
+                eprintln!("Error reading password: {}", err);
+                return EXIT_LOGIN;
+            }
+            Ok(pass) => pass,
+        };
+    }
+
+    let credentials = Credentials::with_password(config.user.clone(), config.pass.clone());
+
+    return match auth::connect(&config, credentials).await {
+        Err(err) => {
+            eprintln!("Login failed: {}", err);
+            EXIT_LOGIN
+        }
+        Ok((_, credentials)) => {
+            eprintln!("Logged in as {}, credentials cached in {}", credentials.username, config.auth.cache_dir.unwrap());
+            eprintln!("`pass` can now be removed from the config");
+            EXIT_OK
+        }
+    };
+}
+
+pub fn list_sources(config: &SpotifmConfig) -> i32 {
+    for uri in config.uris.iter() {
+        println!("{}", uri);
+    }
+
+    for uri in config.rotation.filler.iter() {
+        println!("{} (filler)", uri);
+    }
+
+    return EXIT_OK;
+}
+
+// prints the tracks of `uri`, one per line
+pub async fn resolve(session: &Session, uri: &str) -> i32 {
+    let track_ids = match resolve_uri(session, uri).await {
+        Err(err) => {
+            eprintln!("{}", err);
+            return EXIT_SOURCE;
+        }
+        Ok(track_ids) => track_ids,
+    };
+
+    for track_id in track_ids {
+        match fetch_track(session, track_id).await {
+            Err(err) => eprintln!("{}", err),
+            Ok(track) => println!("{}\t{} - {}", track.id, track.artists.join(", "), track.track),
+        }
+    }
+
+    return EXIT_OK;
+}
+
+// counts the tracks of every configured source
+pub async fn dry_run(session: &Session, config: &SpotifmConfig) -> i32 {
+    let mut code = EXIT_OK;
+
+    for uri in config.uris.iter().chain(config.rotation.filler.iter()) {
+        match resolve_uri(session, uri).await {
+            Err(err) => {
+                eprintln!("{}", err);
+                code = EXIT_SOURCE;
+            }
+            Ok(track_ids) => println!("{}\t{} tracks", uri, track_ids.len()),
+        }
+    }
+
+    return code;
+}
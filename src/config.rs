@@ -1,43 +1,500 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use cron::Schedule;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
 use serde::{Deserialize, Serialize};
-use serde_json;
+use serde_json::{self, Value};
+
+use crate::jingles;
 
 #[derive(Serialize, Clone, Deserialize)]
 pub struct SpotifmConfig {
+    #[serde(default)]
     pub user: String,
+    #[serde(default)]
     pub pass: String,
+    // file holding the password, e.g. a docker secret
+    #[serde(default)]
+    pub pass_file: Option<String>,
+    #[serde(default)]
     pub uris: Vec<String>,
+    #[serde(default)]
     pub announce:  SpotifmAnnounceConfig,
+    #[serde(default)]
     pub elevenlabs: SpotifmElevenLabsCfg,
+    #[serde(default)]
+    pub rotation: SpotifmRotationCfg,
+    #[serde(default)]
+    pub tts: SpotifmTtsCfg,
+    #[serde(default)]
+    pub auth: SpotifmAuthCfg,
+    #[serde(default)]
+    pub reconnect: SpotifmReconnectCfg,
+    #[serde(default)]
+    pub shutdown: SpotifmShutdownCfg,
+    // write announcement edits made over the rest api back to the config file
+    #[serde(default)]
+    pub persist: bool,
+    // reload the config when the file changes, it's always reloaded on SIGHUP
+    #[serde(default)]
+    pub watch: bool,
+    #[serde(skip)]
+    pub path: String,
+    // json pointers of settings that came from the environment or secret files, they aren't saved
+    #[serde(skip)]
+    overridden: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmAuthCfg {
+    pub cache_dir: Option<String>,
+    pub blob: Option<String>,
+    pub token: Option<String>,
+}
+
+impl Default for SpotifmAuthCfg {
+    fn default() -> SpotifmAuthCfg {
+        let home = std::env::var("HOME").map(PathBuf::from).unwrap_or(std::env::temp_dir());
+        return SpotifmAuthCfg {
+            cache_dir: Some(home.join(".cache").join("spotifm").to_string_lossy().to_string()),
+            blob: None,
+            token: None,
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmReconnectCfg {
+    // seconds without audio before the connection is considered lost
+    pub stall_secs: u64,
+    pub backoff_min_ms: u64,
+    pub backoff_max_secs: u64,
+}
+
+impl Default for SpotifmReconnectCfg {
+    fn default() -> SpotifmReconnectCfg {
+        return SpotifmReconnectCfg {
+            stall_secs: 15,
+            backoff_min_ms: 1000,
+            backoff_max_secs: 60,
+        };
+    }
 }
 
 #[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmShutdownCfg {
+    // how long a shutdown may take before spotifm exits anyway
+    pub timeout_secs: u64,
+    pub fade_ms: u32,
+}
+
+impl Default for SpotifmShutdownCfg {
+    fn default() -> SpotifmShutdownCfg {
+        return SpotifmShutdownCfg {
+            timeout_secs: 10,
+            fade_ms: 2000,
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Default)]
 pub struct SpotifmAnnounceConfig {
+    #[serde(default)]
     pub song: SpotifmSongConfig,
+    #[serde(default)]
     pub bumper: SpotifmBumperConfig,
+    #[serde(default)]
+    pub talkover: SpotifmTalkoverCfg,
+    pub listeners_url: Option<String>,
+    #[serde(default)]
+    pub schedule: SpotifmScheduleCfg,
+    #[serde(default)]
+    pub events: SpotifmEventsCfg,
+    #[serde(default)]
+    pub voices: SpotifmVoicesCfg,
+}
+
+// song voices by the language or script of the track title, see `language::detect` for the names
+#[derive(Serialize, Clone, Deserialize, Default)]
+pub struct SpotifmVoicesCfg {
+    #[serde(default)]
+    pub enable: bool,
+    #[serde(default)]
+    pub languages: HashMap<String, String>,
+}
+
+#[derive(Serialize, Clone, Deserialize, Default)]
+pub struct SpotifmEventsCfg {
+    #[serde(default)]
+    pub request: SpotifmEventCfg,
+    #[serde(default)]
+    pub skip: SpotifmEventCfg,
+    #[serde(default)]
+    pub play: SpotifmEventCfg,
+}
+
+#[derive(Serialize, Clone, Deserialize, Default)]
+pub struct SpotifmEventCfg {
+    #[serde(default)]
+    pub enable: bool,
+    #[serde(default)]
+    pub templates: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Deserialize, Default)]
+pub struct SpotifmScheduleCfg {
+    #[serde(default)]
+    pub station_id: SpotifmStationIdCfg,
+    #[serde(default)]
+    pub time_check: SpotifmTimeCheckCfg,
+    #[serde(default)]
+    pub messages: Vec<SpotifmScheduledMessage>,
+}
+
+#[derive(Serialize, Clone, Deserialize, Default)]
+pub struct SpotifmStationIdCfg {
+    #[serde(default)]
+    pub enable: bool,
+    #[serde(default)]
+    pub texts: Vec<String>,
 }
 
 #[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmTimeCheckCfg {
+    pub enable: bool,
+    pub minutes: Vec<u32>,
+    pub template: String,
+}
+
+impl Default for SpotifmTimeCheckCfg {
+    fn default() -> SpotifmTimeCheckCfg {
+        return SpotifmTimeCheckCfg {
+            enable: false,
+            minutes: vec![0],
+            template: "it's {time}".to_string(),
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+pub struct SpotifmScheduledMessage {
+    pub cron: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmTalkoverCfg {
+    pub enable: bool,
+    pub duck_db: f32,
+    pub ramp_down_ms: u32,
+    pub ramp_up_ms: u32,
+}
+
+impl Default for SpotifmTalkoverCfg {
+    fn default() -> SpotifmTalkoverCfg {
+        return SpotifmTalkoverCfg {
+            enable: false,
+            duck_db: 12.0,
+            ramp_down_ms: 500,
+            ramp_up_ms: 1500,
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
 pub struct SpotifmSongConfig {
     pub enable: bool,
+    pub engine: TtsKind,
+    pub templates: Vec<String>,
     pub espeak: SpotifmEspeakCfg,
 }
 
+impl Default for SpotifmSongConfig {
+    fn default() -> SpotifmSongConfig {
+        return SpotifmSongConfig {
+            enable: false,
+            engine: TtsKind::Espeak,
+            templates: vec!["{track} by {artists_joined}".to_string()],
+            espeak: SpotifmEspeakCfg::default(),
+        };
+    }
+}
+
 #[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
 pub struct SpotifmBumperConfig {
+    #[serde(skip)]
     idx: Option<usize>,
     pub enable: bool,
     pub tags: Vec<String>,
     pub freq: usize,
+    pub engine: TtsKind,
     pub espeak: SpotifmEspeakCfg,
+    pub source: BumperSource,
+    pub jingles: SpotifmJinglesCfg,
+}
+
+impl Default for SpotifmBumperConfig {
+    fn default() -> SpotifmBumperConfig {
+        return SpotifmBumperConfig {
+            idx: Some(0),
+            enable: false,
+            tags: Vec::new(),
+            freq: 5,
+            engine: TtsKind::Espeak,
+            espeak: SpotifmEspeakCfg::default(),
+            source: BumperSource::Tags,
+            jingles: SpotifmJinglesCfg::default(),
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BumperSource {
+    Tags,
+    Jingles,
+    Both,
+}
+
+impl Default for BumperSource {
+    fn default() -> BumperSource {
+        return BumperSource::Tags;
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JingleMode {
+    RoundRobin,
+    Weighted,
+}
+
+impl Default for JingleMode {
+    fn default() -> JingleMode {
+        return JingleMode::RoundRobin;
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmJinglesCfg {
+    #[serde(skip)]
+    idx: usize,
+    pub dir: String,
+    pub mode: JingleMode,
+    pub weights: HashMap<String, u32>,
+}
+
+impl Default for SpotifmJinglesCfg {
+    fn default() -> SpotifmJinglesCfg {
+        return SpotifmJinglesCfg {
+            idx: 0,
+            dir: "/jingles".to_string(),
+            mode: JingleMode::RoundRobin,
+            weights: HashMap::new(),
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Default)]
+pub struct SpotifmRotationCfg {
+    #[serde(default)]
+    pub dedupe: bool,
+    #[serde(default)]
+    pub dedupe_isrc: bool,
+    #[serde(default)]
+    pub track_spacing: usize,
+    #[serde(default)]
+    pub artist_spacing: usize,
+    #[serde(default)]
+    pub shuffle: ShuffleMode,
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub on_wrap: OnWrap,
+    #[serde(default)]
+    pub filler: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnWrap {
+    Repeat,
+    Reshuffle,
+    Stop,
+    Filler,
+}
+
+impl Default for OnWrap {
+    fn default() -> OnWrap {
+        return OnWrap::Repeat;
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShuffleMode {
+    Uniform,
+    ArtistSpread,
+    Popularity,
+    LeastRecent,
+}
+
+impl Default for ShuffleMode {
+    fn default() -> ShuffleMode {
+        return ShuffleMode::Uniform;
+    }
+}
+
+impl ShuffleMode {
+    pub fn from_name(name: &str) -> Option<ShuffleMode> {
+        return match name {
+            "uniform" => Some(ShuffleMode::Uniform),
+            "artist_spread" => Some(ShuffleMode::ArtistSpread),
+            "popularity" => Some(ShuffleMode::Popularity),
+            "least_recent" => Some(ShuffleMode::LeastRecent),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TtsKind {
+    Espeak,
+    EspeakNg,
+    Piper,
+    Http,
+    #[serde(rename = "elevenlabs")]
+    ElevenLabs,
+}
+
+impl Default for TtsKind {
+    fn default() -> TtsKind {
+        return TtsKind::Espeak;
+    }
+}
+
+impl TtsKind {
+    pub fn from_name(name: &str) -> Option<TtsKind> {
+        return match name {
+            "espeak" => Some(TtsKind::Espeak),
+            "espeak-ng" => Some(TtsKind::EspeakNg),
+            "piper" => Some(TtsKind::Piper),
+            "http" => Some(TtsKind::Http),
+            "elevenlabs" => Some(TtsKind::ElevenLabs),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmTtsCfg {
+    pub piper: SpotifmPiperCfg,
+    pub http: SpotifmHttpTtsCfg,
+    pub cache_dir: Option<String>,
+    pub cache_max_files: usize,
+}
+
+impl Default for SpotifmTtsCfg {
+    fn default() -> SpotifmTtsCfg {
+        return SpotifmTtsCfg {
+            piper: SpotifmPiperCfg::default(),
+            http: SpotifmHttpTtsCfg::default(),
+            cache_dir: Some(std::env::temp_dir().join("spotifm-tts").to_string_lossy().to_string()),
+            cache_max_files: 1000,
+        };
+    }
 }
 
 #[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmPiperCfg {
+    pub binary: String,
+    pub model: String,
+    pub speaker: Option<u32>,
+    pub length_scale: f32,
+}
+
+impl Default for SpotifmPiperCfg {
+    fn default() -> SpotifmPiperCfg {
+        return SpotifmPiperCfg {
+            binary: "piper".to_string(),
+            model: "".to_string(),
+            speaker: None,
+            length_scale: 1.0,
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpotifmHttpTtsCfg {
+    pub url: String,
+    pub headers: HashMap<String, String>,
+    pub text_field: String,
+    pub params: Value,
+    pub timeout: u64,
+}
+
+impl Default for SpotifmHttpTtsCfg {
+    fn default() -> SpotifmHttpTtsCfg {
+        return SpotifmHttpTtsCfg {
+            url: "".to_string(),
+            headers: HashMap::new(),
+            text_field: "text".to_string(),
+            params: Value::Null,
+            timeout: default_tts_timeout(),
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
 pub struct SpotifmElevenLabsCfg {
     pub key: String,
+    pub key_file: Option<String>,
     pub voice: String,
+    pub model_id: String,
+    pub stability: f32,
+    pub similarity_boost: f32,
+    pub url: String,
+    pub timeout: u64,
+}
+
+impl Default for SpotifmElevenLabsCfg {
+    fn default() -> SpotifmElevenLabsCfg {
+        return SpotifmElevenLabsCfg {
+            key: "".to_string(),
+            key_file: None,
+            voice: "".to_string(),
+            model_id: "eleven_monolingual_v1".to_string(),
+            stability: 0.0,
+            similarity_boost: 0.0,
+            url: "https://api.elevenlabs.io".to_string(),
+            timeout: default_tts_timeout(),
+        };
+    }
+}
+
+fn default_tts_timeout() -> u64 {
+    return 30;
 }
 
+// espeak's own defaults
 #[derive(Serialize, Clone, Deserialize)]
+#[serde(default)]
 pub struct SpotifmEspeakCfg {
     pub speed: u32,
     pub amplitude: u32,
@@ -46,25 +503,619 @@ pub struct SpotifmEspeakCfg {
     pub voice: String,
 }
 
+impl Default for SpotifmEspeakCfg {
+    fn default() -> SpotifmEspeakCfg {
+        return SpotifmEspeakCfg {
+            speed: 175,
+            amplitude: 100,
+            pitch: 50,
+            gap: 0,
+            voice: "en".to_string(),
+        };
+    }
+}
+
 impl SpotifmConfig {
-    pub fn load(path: String) -> SpotifmConfig {
-        let str = std::fs::read_to_string(path)
-        .expect("unable to read config file");
+    // parses and validates the config file, the error lists every problem found
+    pub fn read(path: &str) -> Result<SpotifmConfig, String> {
+        let config = SpotifmConfig::read_unchecked(path)?;
+
+        let problems = config.validate();
+        if problems.len() > 0 {
+            return Err(format!("invalid config file {}:\n  {}", path, problems.join("\n  ")));
+        }
+
+        return Ok(config);
+    }
+
+    // parses the config file and applies the environment and secret files without validating it
+    pub fn read_unchecked(path: &str) -> Result<SpotifmConfig, String> {
+        let str = std::fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+
+        let file = ConfigFormat::from_path(path).parse(&str).map_err(|err| format!("could not parse {}: {}", path, err))?;
+
+        let mut config: SpotifmConfig = serde_path_to_error::deserialize(file).map_err(|err| {
+            format!("invalid config file {}:\n  {}: {}", path, err.path(), err.inner())
+        })?;
 
-        let mut config: SpotifmConfig = serde_json::from_str(&str)
-            .expect("unable to parse config file");
+        // the file with every default filled in, so the environment can set anything and we know its type
+        let mut value = serde_json::to_value(&config).map_err(|err| err.to_string())?;
+        let mut overridden = env_overrides(&mut value);
+
+        if overridden.len() > 0 {
+            config = serde_path_to_error::deserialize(value).map_err(|err| {
+                format!("invalid environment override:\n  {}: {}", err.path(), err.inner())
+            })?;
+        }
+
+        overridden.extend(config.read_secrets(&overridden)?);
+        config.overridden = overridden;
 
         config.announce.bumper.idx = Some(0);
+        config.path = path.to_string();
 
-        return config;
+        return Ok(config);
+    }
+
+    // secrets are taken from, highest precedence first:
+    //   1. SPOTIFM_PASS / SPOTIFM_ELEVENLABS__KEY
+    //   2. the file in `pass_file` / `elevenlabs.key_file` (which can also be set from the environment)
+    //   3. docker secrets at /run/secrets/spotifm_pass / /run/secrets/spotifm_elevenlabs_key
+    //   4. `pass` / `elevenlabs.key` in the config file
+    // returns the json pointers of the secrets read from files
+    fn read_secrets(&mut self, overridden: &[String]) -> Result<Vec<String>, String> {
+        let mut read: Vec<String> = Vec::new();
+
+        if !overridden.contains(&"/pass".to_string()) {
+            match secret(self.pass_file.as_deref(), "spotifm_pass")? {
+                None => {}
+                Some(pass) => {
+                    self.pass = pass;
+                    read.push("/pass".to_string());
+                }
+            }
+        }
+
+        if !overridden.contains(&"/elevenlabs/key".to_string()) {
+            match secret(self.elevenlabs.key_file.as_deref(), "spotifm_elevenlabs_key")? {
+                None => {}
+                Some(key) => {
+                    self.elevenlabs.key = key;
+                    read.push("/elevenlabs/key".to_string());
+                }
+            }
+        }
+
+        return Ok(read);
+    }
+
+    // everything wrong with the config as "json.path: problem"
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        // a token or cached credentials can log in without a password, and cached ones know the user,
+        // only the file is checked, opening the cache would create its directory
+        let cached = match &self.auth.cache_dir {
+            None => false,
+            Some(dir) => std::fs::File::open(Path::new(dir).join("credentials.json")).is_ok(),
+        };
+
+        if self.user.is_empty() && !cached && self.auth.token.is_none() {
+            problems.push("user: missing".to_string());
+        }
+
+        if self.pass.is_empty() && !cached && self.auth.blob.is_none() && self.auth.token.is_none() {
+            problems.push("pass: missing, set it or run `spotifm login` to cache credentials".to_string());
+        }
+
+        if self.uris.len() == 0 {
+            problems.push("uris: no sources to play".to_string());
+        }
+
+        for (i, uri) in self.uris.iter().enumerate() {
+            if !valid_uri(uri) {
+                problems.push(format!("uris[{}]: {} is not a spotify track, artist, album or playlist uri", i, uri));
+            }
+        }
+
+        let song = &self.announce.song;
+        if song.enable && song.templates.len() == 0 {
+            problems.push("announce.song.templates: empty while song announcements are enabled".to_string());
+        }
+        self.validate_engine("announce.song.engine", song.engine, &mut problems);
+
+        let bumper = &self.announce.bumper;
+        if bumper.enable {
+            if bumper.freq == 0 {
+                problems.push("announce.bumper.freq: must be at least 1".to_string());
+            }
+            if bumper.source == BumperSource::Tags && bumper.tags.len() == 0 {
+                problems.push("announce.bumper.tags: empty while bumpers are enabled".to_string());
+            }
+        }
+        self.validate_engine("announce.bumper.engine", bumper.engine, &mut problems);
+
+        if self.announce.talkover.duck_db < 0.0 {
+            problems.push("announce.talkover.duck_db: must not be negative".to_string());
+        }
+
+        for (i, minute) in self.announce.schedule.time_check.minutes.iter().enumerate() {
+            if *minute > 59 {
+                problems.push(format!("announce.schedule.time_check.minutes[{}]: {} is not a minute", i, minute));
+            }
+        }
+
+        for (i, message) in self.announce.schedule.messages.iter().enumerate() {
+            match Schedule::from_str(message.cron.as_str()) {
+                Err(err) => problems.push(format!("announce.schedule.messages[{}].cron: {}", i, err)),
+                Ok(_) => {}
+            }
+        }
+
+        if self.reconnect.stall_secs == 0 {
+            problems.push("reconnect.stall_secs: must be at least 1".to_string());
+        }
+
+        if self.reconnect.backoff_min_ms == 0 {
+            problems.push("reconnect.backoff_min_ms: must be at least 1".to_string());
+        }
+
+        if self.rotation.on_wrap == OnWrap::Filler && self.rotation.filler.len() == 0 {
+            problems.push("rotation.filler: empty while on_wrap is filler".to_string());
+        }
+
+        for (i, uri) in self.rotation.filler.iter().enumerate() {
+            if !valid_uri(uri) {
+                problems.push(format!("rotation.filler[{}]: {} is not a spotify track, artist, album or playlist uri", i, uri));
+            }
+        }
+
+        return problems;
+    }
+
+    // settings the tts engine at `path` can't work without
+    fn validate_engine(&self, path: &str, kind: TtsKind, problems: &mut Vec<String>) {
+        match kind {
+            TtsKind::Espeak | TtsKind::EspeakNg => {}
+            TtsKind::Piper => {
+                if self.tts.piper.model.is_empty() {
+                    problems.push(format!("tts.piper.model: missing, {} is piper", path));
+                }
+            }
+            TtsKind::Http => {
+                if self.tts.http.url.is_empty() {
+                    problems.push(format!("tts.http.url: missing, {} is http", path));
+                }
+            }
+            TtsKind::ElevenLabs => {
+                if self.elevenlabs.key.is_empty() {
+                    problems.push(format!("elevenlabs.key: missing, {} is elevenlabs", path));
+                }
+                if self.elevenlabs.voice.is_empty() {
+                    problems.push(format!("elevenlabs.voice: missing, {} is elevenlabs", path));
+                }
+            }
+        }
+    }
+
+    // takes the settings from a reloaded config which can change without interrupting playback,
+    // returns the names of the sections that changed
+    pub fn apply(&mut self, mut new: SpotifmConfig) -> Vec<&'static str> {
+        let mut changed: Vec<&'static str> = Vec::new();
+
+        self.overridden = new.overridden.clone();
+
+        if self.user != new.user || self.pass != new.pass || differs(&self.auth, &new.auth) {
+            eprintln!("Spotify credentials changed, restart spotifm to use them");
+        }
+
+        if differs(&self.announce, &new.announce) {
+            // keep the bumper and jingle rotation going where it was
+            if self.announce.bumper.tags == new.announce.bumper.tags {
+                new.announce.bumper.idx = self.announce.bumper.idx;
+            }
+            new.announce.bumper.jingles.idx = self.announce.bumper.jingles.idx;
+            self.announce = new.announce;
+            changed.push("announce");
+        }
+
+        if self.uris != new.uris {
+            self.uris = new.uris;
+            changed.push("uris");
+        }
+
+        if differs(&self.rotation, &new.rotation) {
+            self.rotation = new.rotation;
+            changed.push("rotation");
+        }
+
+        if differs(&self.tts, &new.tts) {
+            self.tts = new.tts;
+            changed.push("tts");
+        }
+
+        if differs(&self.shutdown, &new.shutdown) {
+            self.shutdown = new.shutdown;
+            changed.push("shutdown");
+        }
+
+        if differs(&self.reconnect, &new.reconnect) {
+            self.reconnect = new.reconnect;
+            changed.push("reconnect");
+        }
+
+        if differs(&self.elevenlabs, &new.elevenlabs) {
+            self.elevenlabs = new.elevenlabs;
+            changed.push("elevenlabs");
+        }
+
+        if self.persist != new.persist {
+            self.persist = new.persist;
+            changed.push("persist");
+        }
+
+        // the watcher checks this before every reload
+        if self.watch != new.watch {
+            self.watch = new.watch;
+            changed.push("watch");
+        }
+
+        return changed;
+    }
+
+    // writes the config back to the file it was loaded from, keys spotifm doesn't know about are kept
+    pub fn save(&self) -> Result<(), String> {
+        self.write_back()?;
+
+        // so the watcher doesn't reload the station's own write
+        match std::fs::metadata(&self.path).and_then(|x| x.modified()) {
+            Err(_) => {}
+            Ok(modified) => *SAVED.lock().unwrap() = Some((self.path.clone(), modified)),
+        }
+
+        return Ok(());
+    }
+
+    fn write_back(&self) -> Result<(), String> {
+        let format = ConfigFormat::from_path(self.path.as_str());
+
+        let mut file: Value = match std::fs::read_to_string(&self.path) {
+            Err(_) => Value::Object(serde_json::Map::new()),
+            Ok(str) => format.parse(&str).map_err(|err| format!("could not parse {}: {}", self.path, err))?,
+        };
+
+        let mut value = serde_json::to_value(self).map_err(|err| err.to_string())?;
+
+        // settings from the environment and secret files stay where they came from
+        for pointer in self.overridden.iter() {
+            restore(&mut value, &file, pointer);
+        }
+
+        // defaults the file leaves out stay out, so machine specific ones like `auth.cache_dir` aren't pinned
+        let defaults = settings(file.clone()).unwrap_or(Value::Null);
+        merge(&mut file, value, &defaults);
+
+        let str = format.render(&file)?;
+
+        // write next to the file and rename over it so a crash can't leave half a config behind
+        let tmp = format!("{}.tmp", self.path);
+        let err = match std::fs::write(&tmp, &str).and_then(|_| std::fs::rename(&tmp, &self.path)) {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        let _ = std::fs::remove_file(&tmp);
+
+        // a single bind-mounted file (docker) can't be replaced and its directory may not be writable,
+        // anything else (e.g. a full disk) would fail in place too, after truncating the config
+        if !unreplaceable(&err) {
+            return Err(format!("could not replace {}: {}", self.path, err));
+        }
+
+        eprintln!("Could not replace {} ({}), writing it in place", self.path, err);
+        return std::fs::write(&self.path, &str).map_err(|err| format!("could not write {}: {}", self.path, err));
+    }
+
+    // the config as json with passwords, api keys and http headers blanked out
+    pub fn redacted(&self) -> Value {
+        let mut value = serde_json::to_value(self).unwrap_or(Value::Null);
+
+        for pointer in ["/pass", "/elevenlabs/key", "/auth/blob", "/auth/token"] {
+            match value.pointer_mut(pointer) {
+                Some(secret) if secret.is_string() => *secret = Value::String(REDACTED.to_string()),
+                _ => {}
+            }
+        }
+
+        match value.pointer_mut("/tts/http/headers").and_then(|x| x.as_object_mut()) {
+            None => {}
+            Some(headers) => {
+                for header in headers.values_mut() {
+                    *header = Value::String(REDACTED.to_string());
+                }
+            }
+        }
+
+        return value;
+    }
+}
+
+// config files are json, toml or yaml depending on their extension
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &str) -> ConfigFormat {
+        let ext = Path::new(path).extension().and_then(|x| x.to_str()).map(|x| x.to_lowercase());
+
+        return match ext.as_deref() {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        };
+    }
+
+    pub fn parse(&self, str: &str) -> Result<Value, String> {
+        return match self {
+            ConfigFormat::Json => serde_json::from_str(str).map_err(|err| err.to_string()),
+            ConfigFormat::Toml => toml::from_str(str).map_err(|err| err.to_string()),
+            ConfigFormat::Yaml => serde_yaml::from_str(str).map_err(|err| err.to_string()),
+        };
+    }
+
+    pub fn render(&self, value: &Value) -> Result<String, String> {
+        return match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(value).map_err(|err| err.to_string()),
+            ConfigFormat::Toml => {
+                // toml has no null, unset settings are left out instead
+                let table = toml::Value::try_from(without_nulls(value.clone())).map_err(|err| err.to_string())?;
+                toml::to_string_pretty(&table).map_err(|err| err.to_string())
+            }
+            ConfigFormat::Yaml => serde_yaml::to_string(value).map_err(|err| err.to_string()),
+        };
+    }
+}
+
+// rewrites the config file `input` as `output`, the format of each is taken from its extension
+pub fn convert(input: &str, output: &str) -> Result<(), String> {
+    let str = std::fs::read_to_string(input).map_err(|err| format!("could not read {}: {}", input, err))?;
+    let value = ConfigFormat::from_path(input).parse(&str).map_err(|err| format!("could not parse {}: {}", input, err))?;
+
+    let format = ConfigFormat::from_path(output);
+    let converted = format.render(&value)?;
+
+    let expected = match format {
+        ConfigFormat::Toml => without_nulls(value.clone()),
+        _ => value.clone(),
+    };
+
+    // make sure nothing was lost on the way
+    let parsed = format.parse(&converted)?;
+    if parsed != expected {
+        return Err(format!("{} doesn't read back the same as {}", output, input));
+    }
+
+    // a dropped null reads back as the default, which isn't always unset (e.g. `auth.cache_dir`)
+    if settings(value)? != settings(parsed)? {
+        return Err(format!("{} sets settings to null, {} can't express that and would read them back as their defaults", input, output));
+    }
+
+    return std::fs::write(output, converted).map_err(|err| format!("could not write {}: {}", output, err));
+}
+
+// the config `value` describes, with every default filled in
+fn settings(value: Value) -> Result<Value, String> {
+    let config: SpotifmConfig = serde_path_to_error::deserialize(value)
+        .map_err(|err| format!("invalid config:\n  {}: {}", err.path(), err.inner()))?;
+    return serde_json::to_value(&config).map_err(|err| err.to_string());
+}
+
+fn without_nulls(value: Value) -> Value {
+    return match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(without_nulls).collect()),
+        value => value,
+    };
+}
+
+const REDACTED: &str = "********";
+
+// the file and modification time of the last save
+static SAVED: Mutex<Option<(String, SystemTime)>> = Mutex::new(None);
+
+// true if `path` was last modified by `save`
+pub fn saved_at(path: &str, modified: SystemTime) -> bool {
+    return *SAVED.lock().unwrap() == Some((path.to_string(), modified));
+}
+
+const ENV_PREFIX: &str = "SPOTIFM_";
+
+const SECRETS_DIR: &str = "/run/secrets";
+
+// applies SPOTIFM_* environment variables to `value`, `__` separates nested keys so
+// SPOTIFM_ANNOUNCE__BUMPER__FREQ=3 sets `announce.bumper.freq`, returns the json pointers that were set
+fn env_overrides(value: &mut Value) -> Vec<String> {
+    let mut overridden: Vec<String> = Vec::new();
+
+    let mut vars: Vec<(String, String)> = std::env::vars().filter(|(name, _)| name.starts_with(ENV_PREFIX)).collect();
+    vars.sort();
+
+    for (name, raw) in vars {
+        let keys: Vec<String> = name[ENV_PREFIX.len()..].to_lowercase().split("__").map(|x| x.to_string()).collect();
+        let pointer = format!("/{}", keys.join("/"));
+
+        let parent_pointer: String = keys[..keys.len() - 1].iter().map(|key| format!("/{}", key)).collect();
+
+        let parent = match value.pointer_mut(parent_pointer.as_str()) {
+            Some(Value::Object(parent)) => parent,
+            _ => {
+                eprintln!("Ignoring {}, there is no such setting", name);
+                continue;
+            }
+        };
+
+        let key = keys[keys.len() - 1].clone();
+
+        // every setting is in `value` with its default, anything else would be dropped when deserializing
+        if !parent.contains_key(&key) {
+            eprintln!("Ignoring {}, there is no such setting", name);
+            continue;
+        }
+
+        // strings are taken as they are, anything else is parsed as json
+        let setting = match parent.get(&key) {
+            Some(Value::String(_)) => Value::String(raw),
+            _ => serde_json::from_str(raw.as_str()).unwrap_or(Value::String(raw)),
+        };
+
+        parent.insert(key, setting);
+        overridden.push(pointer);
+    }
+
+    return overridden;
+}
+
+// a secret from `file`, or from the docker secret `name` when no file is set
+fn secret(file: Option<&str>, name: &str) -> Result<Option<String>, String> {
+    let path = match file {
+        Some(file) => PathBuf::from(file),
+        None => {
+            let path = PathBuf::from(SECRETS_DIR).join(name);
+            if !path.is_file() {
+                return Ok(None);
+            }
+            path
+        }
+    };
+
+    return match std::fs::read_to_string(&path) {
+        Err(err) => Err(format!("could not read secret {}: {}", path.display(), err)),
+        Ok(secret) => Ok(Some(secret.trim_end_matches(&['\r', '\n'][..]).to_string())),
+    };
+}
+
+// puts the setting at `pointer` back to how it is in `file`, or removes it if the file doesn't have it
+fn restore(value: &mut Value, file: &Value, pointer: &str) {
+    match file.pointer(pointer) {
+        Some(original) => match value.pointer_mut(pointer) {
+            None => {}
+            Some(setting) => *setting = original.clone(),
+        },
+        None => {
+            let (parent, key) = pointer.rsplit_once('/').unwrap_or(("", pointer));
+            match value.pointer_mut(parent).and_then(|x| x.as_object_mut()) {
+                None => {}
+                Some(parent) => {
+                    parent.remove(key);
+                }
+            }
+        }
+    }
+}
+
+fn valid_uri(uri: &str) -> bool {
+    let parts: Vec<&str> = uri.split(':').collect();
+    return parts.len() == 3
+        && parts[0] == "spotify"
+        && ["track", "artist", "album", "playlist"].contains(&parts[1])
+        && parts[2].len() > 0;
+}
+
+fn differs<T: Serialize>(a: &T, b: &T) -> bool {
+    return serde_json::to_value(a).ok() != serde_json::to_value(b).ok();
+}
+
+// copies `from` into `into`, objects are merged key by key and everything else is replaced,
+// keys missing from `into` are only added where they differ from `defaults`
+fn merge(into: &mut Value, from: Value, defaults: &Value) {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (key, value) in from {
+                let default = defaults.get(&key).unwrap_or(&Value::Null);
+
+                match into.get_mut(&key) {
+                    Some(existing) => merge(existing, value, default),
+                    None if value == *default => {}
+                    None => {
+                        let mut added = match (&value, default) {
+                            (Value::Object(_), Value::Object(_)) => Value::Object(serde_json::Map::new()),
+                            _ => Value::Null,
+                        };
+                        merge(&mut added, value, default);
+                        if added.as_object().map(|x| x.is_empty()) != Some(true) {
+                            into.insert(key, added);
+                        }
+                    }
+                }
+            }
+        }
+        (into, from) => *into = from,
+    }
+}
+
+// errno values (linux) for a config file that can't be replaced but may still be writable:
+// EACCES and EROFS creating the temp file, EBUSY and EXDEV renaming it over a mount point
+const EACCES: i32 = 13;
+const EROFS: i32 = 30;
+const EBUSY: i32 = 16;
+const EXDEV: i32 = 18;
+
+fn unreplaceable(err: &std::io::Error) -> bool {
+    return matches!(err.raw_os_error(), Some(EACCES) | Some(EROFS) | Some(EBUSY) | Some(EXDEV));
+}
+
+impl SpotifmJinglesCfg {
+    // files without a weight count as 1
+    pub fn weight(&self, name: &str) -> u32 {
+        return *self.weights.get(name).unwrap_or(&1);
+    }
+
+    pub fn next(&mut self) -> Option<PathBuf> {
+        let names = match jingles::list(self.dir.as_str()) {
+            Err(err) => {
+                eprintln!("Error loading jingles: {}", err);
+                return None;
+            }
+            Ok(names) => names,
+        };
+
+        if names.len() == 0 {
+            return None;
+        }
+
+        let name = match self.mode {
+            JingleMode::RoundRobin => {
+                let name = names[self.idx % names.len()].clone();
+                self.idx = (self.idx + 1) % names.len();
+                name
+            }
+            JingleMode::Weighted => names
+                .choose_weighted(&mut thread_rng(), |name| self.weight(name))
+                .ok()?
+                .clone(),
+        };
+
+        return jingles::path(self.dir.as_str(), name.as_str()).ok();
     }
 }
 
 impl SpotifmBumperConfig {
-    pub fn next(&mut self) -> String {
-        let tag = self.tags[self.idx.unwrap()].clone();
-        self.idx = Some((self.idx.unwrap() + 1) % self.tags.len());
-        return tag;
+    pub fn next(&mut self) -> Option<String> {
+        if self.tags.len() == 0 {
+            return None;
+        }
+
+        let idx = self.idx.unwrap_or(0) % self.tags.len();
+        self.idx = Some((idx + 1) % self.tags.len());
+        return Some(self.tags[idx].clone());
     }
 
     pub fn clear_tags(&mut self) {
@@ -80,4 +1131,95 @@ impl SpotifmBumperConfig {
         self.idx = Some(0);
         self.tags = tags;
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> String {
+        return format!("{}/config.json.example", env!("CARGO_MANIFEST_DIR"));
+    }
+
+    fn tmp(name: &str) -> String {
+        return std::env::temp_dir()
+            .join(format!("spotifm-test-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string();
+    }
+
+    fn settings_of(path: &str) -> Value {
+        return serde_json::to_value(SpotifmConfig::read_unchecked(path).unwrap()).unwrap();
+    }
+
+    fn round_trip(input: &str, ext: &str) {
+        let converted = tmp(&format!("{}.{}", ext, ext));
+        let back = tmp(&format!("{}-back.json", ext));
+
+        convert(input, converted.as_str()).unwrap();
+        convert(converted.as_str(), back.as_str()).unwrap();
+
+        let expected = settings_of(input);
+        assert_eq!(settings_of(converted.as_str()), expected);
+        assert_eq!(settings_of(back.as_str()), expected);
+
+        let _ = std::fs::remove_file(converted);
+        let _ = std::fs::remove_file(back);
+    }
+
+    #[test]
+    fn example_round_trips_through_toml() {
+        round_trip(example().as_str(), "toml");
+    }
+
+    #[test]
+    fn example_round_trips_through_yaml() {
+        round_trip(example().as_str(), "yaml");
+    }
+
+    #[test]
+    fn toml_leaves_out_nulls() {
+        let mut value: Value = serde_json::from_str(&std::fs::read_to_string(example()).unwrap()).unwrap();
+        value["rotation"] = serde_json::json!({ "seed": null, "shuffle": "uniform" });
+        value["announce"]["listeners_url"] = Value::Null;
+
+        let input = tmp("nulls.json");
+        std::fs::write(&input, value.to_string()).unwrap();
+
+        round_trip(input.as_str(), "toml");
+
+        let _ = std::fs::remove_file(input);
+    }
+
+    #[test]
+    fn toml_rejects_nulls_with_defaults() {
+        let mut value: Value = serde_json::from_str(&std::fs::read_to_string(example()).unwrap()).unwrap();
+        value["auth"] = serde_json::json!({ "cache_dir": null });
+
+        let input = tmp("cache-null.json");
+        let output = tmp("cache-null.toml");
+        std::fs::write(&input, value.to_string()).unwrap();
+
+        assert!(convert(input.as_str(), output.as_str()).is_err());
+        assert!(!Path::new(&output).exists());
+
+        let _ = std::fs::remove_file(input);
+    }
+
+    #[test]
+    fn save_writes_back_changes_only() {
+        let path = tmp("save.json");
+        std::fs::write(&path, r#"{ "user": "user", "pass": "pass", "uris": ["spotify:track:abc"] }"#).unwrap();
+
+        let mut config = SpotifmConfig::read_unchecked(path.as_str()).unwrap();
+        config.announce.bumper.freq = 3;
+        config.save().unwrap();
+
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["announce"]["bumper"]["freq"], 3);
+        assert_eq!(saved["user"], "user");
+        assert!(saved.get("auth").is_none());
+        assert!(saved.get("tts").is_none());
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -2,13 +2,26 @@ use librespot::core::session::Session;
 use librespot::core::spotify_id::SpotifyId;
 use librespot::metadata::{Album, Artist, Metadata, Playlist, Track};
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::runtime::Runtime;
 use rustbreak::{deser::Ron, MemoryDatabase, RustbreakError};
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use chrono::Utc;
+use rspotify::{model::TrackId, prelude::*, AuthCodeSpotify};
+
+use crate::auth;
+use crate::config::{OnWrap, ShuffleMode, SpotifmRotationCfg};
+use crate::shuffle;
+
+// how many played tracks are remembered for the rotation rules
+const HISTORY_LEN: usize = 200;
+
+// the `unavailable` reason for tracks librespot failed to load, which can be a passing network or session problem
+pub const LOAD_FAILED: &str = "could not be loaded";
+
+// queue entry ids, unique for the lifetime of the process
+static NEXT_RID: AtomicU64 = AtomicU64::new(1);
 
 #[derive(Clone)]
 pub struct SpotifyDatabase {
@@ -19,14 +32,26 @@ pub struct SpotifyDatabase {
 pub struct SpotifyState {
     pub queue: Vec<SpotifyTrack>,
     pub queue_position: usize,
+    pub history: Vec<SpotifyTrack>,
 }
 
 #[derive(Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct SpotifyTrack {
     pub id: String,
-    pub rid: u128,
+    pub rid: u64,
     pub track: String,
     pub artists: Vec<String>,
+    pub isrc: Option<String>,
+    pub popularity: Option<u32>,
+    pub requester: Option<String>,
+    pub via: Option<String>,
+    pub last_played: Option<i64>,
+    // why the track can't be played, it's skipped over
+    pub unavailable: Option<String>,
+    // the original id when an available alternative of the track is played instead
+    pub relinked_from: Option<String>,
+    // a play shout-out was given, so the request isn't announced again
+    pub shouted_out: bool,
 }
 
 impl SpotifyState {
@@ -34,6 +59,7 @@ impl SpotifyState {
         return SpotifyState {
             queue: Vec::new(),
             queue_position: 0,
+            history: Vec::new(),
         };
     }
 }
@@ -42,15 +68,32 @@ impl SpotifyTrack {
     pub fn new(id: String, track: String, artists: Vec<String>) -> SpotifyTrack {
         return SpotifyTrack {
             id: id,
-            rid: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis(),
+            rid: NEXT_RID.fetch_add(1, Ordering::Relaxed),
             track: track,
             artists: artists,
+            isrc: None,
+            popularity: None,
+            requester: None,
+            via: None,
+            last_played: None,
+            unavailable: None,
+            relinked_from: None,
+            shouted_out: false,
         };
     }
 
+    // a listener request that hasn't played yet
+    pub fn is_pending(&self) -> bool {
+        return self.requester.is_some() && self.last_played.is_none();
+    }
+
+    pub fn same_song(&self, other: &SpotifyTrack, isrc: bool) -> bool {
+        if self.id == other.id {
+            return true;
+        }
+        return isrc && self.isrc.is_some() && self.isrc == other.isrc;
+    }
+
     pub fn spotify_id(&self) -> SpotifyId {
         return SpotifyId::from_base62(self.id.as_str()).unwrap();
     }
@@ -74,30 +117,24 @@ impl SpotifyDatabase {
         };
     }
 
-    pub fn shuffle(&self) -> Result<SpotifyState, String> {
+    pub fn shuffle(&self, rules: &SpotifmRotationCfg, mode: ShuffleMode) -> Result<SpotifyState, String> {
         return match self.read() {
             Err(err) => Err(err.to_string()),
             Ok(mut state) => {
-                let current_id = state.queue
-                    .get(state.queue_position)
-                    .unwrap()
-                    .id
-                    .clone();
-                state.queue.shuffle(&mut thread_rng());
-                state.queue_position = state.queue
-                    .iter()
-                    .position(|x| x.id == current_id)
-                    .unwrap();
+                reorder(rules, mode, &mut state);
                 self.write(state.clone());
                 return Ok(state);
             }
         };
     }
 
-    pub fn add_track(&self, track: SpotifyTrack) -> Result<SpotifyState, String> {
+    pub fn add_track(&self, track: SpotifyTrack, rules: &SpotifmRotationCfg) -> Result<SpotifyState, String> {
         return match self.read() {
             Err(err) => Err(err.to_string()),
             Ok(mut state) => {
+                if rules.dedupe && state.queue.iter().any(|x| x.same_song(&track, rules.dedupe_isrc)) {
+                    return Ok(state);
+                }
                 state.queue.insert(state.queue.len(), track);
                 self.write(state.clone());
                 return Ok(state);
@@ -109,15 +146,15 @@ impl SpotifyDatabase {
         return match self.read() {
             Err(err) => Err(err.to_string()),
             Ok(mut state) => {
-                let current_id = state.queue.get(state.queue_position).unwrap().id.clone();
+                let current_rid = state.queue.get(state.queue_position).unwrap().rid;
                 state.queue = state
                     .queue
                     .iter()
-                    .filter(|x| x.id != track.id)
+                    .filter(|x| x.rid == current_rid || x.id != track.id)
                     .map(|x| x.clone())
                     .collect();
 
-                state.queue_position = state.queue.iter().position(|x| x.id == current_id).unwrap();
+                state.queue_position = state.queue.iter().position(|x| x.rid == current_rid).unwrap();
 
                 let new_pos: usize = if state.queue.len() > 0 {
                     state.queue_position + 1
@@ -131,6 +168,59 @@ impl SpotifyDatabase {
         };
     }
 
+    pub fn insert_tracks(&self, tracks: Vec<SpotifyTrack>, next: bool) -> Result<Vec<SpotifyTrack>, String> {
+        return match self.read() {
+            Err(err) => Err(err.to_string()),
+            Ok(mut state) => {
+                let pos: usize = if next && state.queue.len() > 0 {
+                    state.queue_position + 1
+                } else {
+                    state.queue.len()
+                };
+                state.queue.splice(pos..pos, tracks.clone());
+                self.write(state);
+                return Ok(tracks);
+            }
+        };
+    }
+
+    pub fn get_track(&self, rid: u64) -> Result<SpotifyTrack, String> {
+        return match self.read() {
+            Err(err) => Err(err.to_string()),
+            Ok(state) => {
+                return match state.queue.iter().find(|x| x.rid == rid) {
+                    None => Err(format!("no queue entry with rid {}", rid)),
+                    Some(track) => Ok(track.clone()),
+                };
+            }
+        };
+    }
+
+    pub fn remove_track(&self, rid: u64) -> Result<SpotifyTrack, String> {
+        return match self.read() {
+            Err(err) => Err(err.to_string()),
+            Ok(mut state) => {
+                let idx = match state.queue.iter().position(|x| x.rid == rid) {
+                    None => return Err(format!("no queue entry with rid {}", rid)),
+                    Some(idx) => idx,
+                };
+
+                if idx == state.queue_position {
+                    return Err("cannot remove the current track".to_string());
+                }
+
+                let track = state.queue.remove(idx);
+
+                if idx < state.queue_position {
+                    state.queue_position -= 1;
+                }
+
+                self.write(state);
+                return Ok(track);
+            }
+        };
+    }
+
     pub fn prev_track(&self) -> Result<SpotifyTrack, String> {
         return match self.read() {
             Err(err) => Err(err.to_string()),
@@ -171,16 +261,74 @@ impl SpotifyDatabase {
         };
     }
 
-    pub fn advance_track(&self) {
+    pub fn has_next(&self) -> bool {
         return match self.read() {
-            Err(_) => {}
+            Err(_) => false,
+            Ok(state) => state.queue_position + 1 < state.queue.len(),
+        };
+    }
+
+    // returns false if the end of the playlist was reached and `on_wrap` doesn't start it over
+    pub fn advance_track(&self, rules: &SpotifmRotationCfg) -> bool {
+        return match self.read() {
+            Err(_) => false,
             Ok(mut state) => {
-                if state.queue_position < state.queue.len() - 1 {
-                    state.queue_position += 1;
+                let next = if state.queue_position < state.queue.len() - 1 {
+                    state.queue_position + 1
                 } else {
-                    state.queue_position = 0;
+                    match rules.on_wrap {
+                        OnWrap::Repeat => 0,
+                        OnWrap::Reshuffle => {
+                            reorder(rules, rules.shuffle, &mut state);
+                            if state.queue.len() > 1 { 1 } else { 0 }
+                        }
+                        OnWrap::Stop | OnWrap::Filler => return false,
+                    }
+                };
+
+                settle(rules, &mut state, next);
+                state.queue_position = next;
+                state.queue[next].last_played = Some(Utc::now().timestamp());
+                state.history.push(state.queue[next].clone());
+                if state.history.len() > HISTORY_LEN {
+                    state.history.remove(0);
+                }
+
+                // settled now so the preload and the pre-rendered announcement get the track that plays next
+                if next + 1 < state.queue.len() {
+                    settle(rules, &mut state, next + 1);
+                }
+
+                self.write(state.clone());
+                return true;
+            }
+        };
+    }
+
+    // marks every queue entry of track `id` as unplayable
+    pub fn mark_unavailable(&self, id: &str, reason: &str) -> Result<SpotifyState, String> {
+        return match self.read() {
+            Err(err) => Err(err.to_string()),
+            Ok(mut state) => {
+                for track in state.queue.iter_mut().filter(|track| track.id == id) {
+                    track.unavailable = Some(reason.to_string());
+                }
+                self.write(state.clone());
+                return Ok(state);
+            }
+        };
+    }
+
+    // gives tracks marked unavailable for `reason` another chance
+    pub fn clear_unavailable(&self, reason: &str) -> Result<SpotifyState, String> {
+        return match self.read() {
+            Err(err) => Err(err.to_string()),
+            Ok(mut state) => {
+                for track in state.queue.iter_mut().filter(|track| track.unavailable.as_deref() == Some(reason)) {
+                    track.unavailable = None;
                 }
                 self.write(state.clone());
+                return Ok(state);
             }
         };
     }
@@ -192,6 +340,7 @@ impl SpotifyDatabase {
             .write(|db| {
                 db.queue = state.queue;
                 db.queue_position = state.queue_position;
+                db.history = state.history;
             })
             .expect("error writing to in-memory database")
     }
@@ -207,68 +356,204 @@ impl SpotifyDatabase {
     }
 }
 
-pub fn populate(uris: Vec<String>, session: Arc<Mutex<Session>>, db: SpotifyDatabase) {
-    thread::spawn(move || {
+// shuffles everything after the current track, which ends up first in the queue
+fn reorder(rules: &SpotifmRotationCfg, mode: ShuffleMode, state: &mut SpotifyState) {
+    let position = state.queue_position;
+    state.queue.rotate_left(position);
+    state.queue_position = 0;
+
+    // pending listener requests keep their place, everything else is reordered around them
+    let slots: Vec<usize> = (1..state.queue.len())
+        .filter(|&idx| !state.queue[idx].is_pending())
+        .collect();
+    let tracks: Vec<SpotifyTrack> = slots.iter().map(|&idx| state.queue[idx].clone()).collect();
+    let ordered = shuffle::order(mode, tracks, &mut shuffle::rng(rules.seed));
+
+    for (idx, track) in slots.into_iter().zip(ordered) {
+        state.queue[idx] = track;
+    }
+
+    spread(rules, &mut state.queue, 1, &state.history);
+}
+
+// moves the track that should play at `next` there, given what was played before it
+fn settle(rules: &SpotifmRotationCfg, state: &mut SpotifyState, next: usize) {
+    // pull the first upcoming track that satisfies the rotation rules forward
+    if !state.queue[next].is_pending() && conflicts(rules, &state.history, &state.queue[next]) {
+        match (next + 1..state.queue.len()).find(|&idx| {
+            let track = &state.queue[idx];
+            track.unavailable.is_none() && !track.is_pending() && !conflicts(rules, &state.history, track)
+        })
+        {
+            None => {}
+            Some(idx) => {
+                let track = state.queue.remove(idx);
+                state.queue.insert(next, track);
+            }
+        }
+    }
+
+    // unplayable tracks stay in the queue but are passed over
+    if state.queue[next].unavailable.is_some() {
+        match (next + 1..state.queue.len()).find(|&idx| state.queue[idx].unavailable.is_none()) {
+            None => {}
+            Some(idx) => {
+                let track = state.queue.remove(idx);
+                state.queue.insert(next, track);
+            }
+        }
+    }
+}
+
+// true if playing `track` after `recent` (oldest first) would break the rotation rules
+fn conflicts(rules: &SpotifmRotationCfg, recent: &[SpotifyTrack], track: &SpotifyTrack) -> bool {
+    let tracks = &recent[recent.len().saturating_sub(rules.track_spacing)..];
+    let artists = &recent[recent.len().saturating_sub(rules.artist_spacing)..];
+
+    return tracks.iter().any(|x| x.same_song(track, rules.dedupe_isrc))
+        || artists
+            .iter()
+            .any(|x| x.artists.iter().any(|artist| track.artists.contains(artist)));
+}
+
+// reorders queue[start..] so each track respects the rotation rules where possible,
+// pending listener requests are never moved
+fn spread(rules: &SpotifmRotationCfg, queue: &mut Vec<SpotifyTrack>, start: usize, history: &[SpotifyTrack]) {
+    let mut recent = history.to_vec();
+
+    for idx in start..queue.len() {
+        if !queue[idx].is_pending() && conflicts(rules, &recent, &queue[idx]) {
+            match (idx + 1..queue.len())
+                .find(|&other| !queue[other].is_pending() && !conflicts(rules, &recent, &queue[other]))
+            {
+                None => {}
+                Some(other) => queue.swap(idx, other),
+            }
+        }
+        recent.push(queue[idx].clone());
+    }
+}
+
+pub async fn resolve_uri(session: &Session, uri: &str) -> Result<Vec<SpotifyId>, String> {
+    let spotify_uri = uri.split(":").collect::<Vec<&str>>();
+    let spotify_id = match SpotifyId::from_uri(uri) {
+        Err(_) => return Err(format!("malformed Spotify URI: {}", uri)),
+        Ok(id) => id,
+    };
+
+    return match spotify_uri.get(1) {
+        Some(&"track") => Ok(vec![spotify_id]),
+        Some(&"artist") => match Artist::get(session, spotify_id).await {
+            Err(_) => Err(format!("could not fetch artist {}", uri)),
+            Ok(list) => Ok(list.top_tracks),
+        },
+        Some(&"album") => match Album::get(session, spotify_id).await {
+            Err(_) => Err(format!("could not fetch album {}", uri)),
+            Ok(list) => Ok(list.tracks),
+        },
+        Some(&"playlist") => match Playlist::get(session, spotify_id).await {
+            Err(_) => Err(format!("could not fetch playlist {}", uri)),
+            Ok(list) => Ok(list.tracks),
+        },
+        _ => Err(format!("malformed Spotify URI: {}", uri)),
+    };
+}
+
+pub async fn fetch_track(session: &Session, track_id: SpotifyId) -> Result<SpotifyTrack, String> {
+    let mut track =
+        SpotifyTrack::new(track_id.to_base62().unwrap(), "".to_string(), Vec::new());
+
+    return match Track::get(session, track_id).await {
+        Err(_) => Err(format!("could not fetch track {}", track.id)),
+        Ok(track_info) => {
+            track.track = track_info.name;
+
+            // region restricted tracks often have a playable copy on another release
+            if !track_info.available {
+                for alternative in track_info.alternatives {
+                    match Track::get(session, alternative).await {
+                        Ok(alternative_info) if alternative_info.available => {
+                            track.relinked_from = Some(track.id.clone());
+                            track.id = alternative.to_base62().unwrap();
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+
+                if track.relinked_from.is_none() {
+                    track.unavailable = Some("not available in this region".to_string());
+                }
+            }
+
+            for id in track_info.artists {
+                match Artist::get(session, id).await {
+                    Err(_) => {}
+                    Ok(artist) => track.artists.push(artist.name),
+                }
+            }
+            Ok(track)
+        }
+    };
+}
+
+// fills in the isrc and popularity of `track`, librespot's metadata carries neither so they come from the web api
+pub fn fetch_web_details(spotify: &AuthCodeSpotify, track: &mut SpotifyTrack) {
+    let track_id = match TrackId::from_id(track.id.as_str()) {
+        Err(_) => return,
+        Ok(track_id) => track_id,
+    };
+
+    match spotify.track(track_id) {
+        Err(err) => eprintln!("Error fetching details for {}: {}", track.id, err),
+        Ok(details) => {
+            track.isrc = details.external_ids.get("isrc").cloned();
+            track.popularity = Some(details.popularity);
+        }
+    }
+}
+
+pub fn populate(uris: Vec<String>, rules: SpotifmRotationCfg, session: Arc<Mutex<Session>>, db: SpotifyDatabase) -> thread::JoinHandle<()> {
+    return thread::spawn(move || {
         let rt = Runtime::new().unwrap();
         rt.block_on(async move {
             for uri in uris {
-                let spotify_uri = uri.split(":").collect::<Vec<&str>>();
-                let spotify_id = SpotifyId::from_uri(uri.as_str()).unwrap();
-
-                let mut tracks: Vec<SpotifyId> = Vec::new();
-
                 let session = session.lock().unwrap().clone();
 
-                match *spotify_uri.get(1).unwrap() {
-                    "track" => {
-                        tracks.push(spotify_id);
-                    }
-                    "artist" => {
-                        let list = Artist::get(&session, spotify_id).await.unwrap();
-                        tracks = list
-                            .top_tracks
-                            .iter()
-                            .map(|x| x.clone())
-                            .collect::<Vec<SpotifyId>>();
-                    }
-                    "album" => {
-                        let list = Album::get(&session, spotify_id).await.unwrap();
-                        tracks = list
-                            .tracks
-                            .iter()
-                            .map(|x| x.clone())
-                            .collect::<Vec<SpotifyId>>();
-                    }
-                    "playlist" => {
-                        let list = Playlist::get(&session, spotify_id).await.unwrap();
-                        tracks = list
-                            .tracks
-                            .iter()
-                            .map(|x| x.clone())
-                            .collect::<Vec<SpotifyId>>();
-                    }
-                    _ => {
-                        panic!("Malformed Spotify URI")
+                let tracks = match resolve_uri(&session, uri.as_str()).await {
+                    Err(err) => {
+                        eprintln!("Error loading {}: {}", uri, err);
+                        continue;
                     }
+                    Ok(tracks) => tracks,
                 };
 
-                for track_id in tracks {
-                    let session = session.clone();
-                    let mut track =
-                        SpotifyTrack::new(track_id.to_base62().unwrap(), "".to_string(), Vec::new());
+                // only needed to match up releases of the same recording or to shuffle by popularity
+                let spotify = match rules.dedupe_isrc || rules.shuffle == ShuffleMode::Popularity {
+                    false => None,
+                    true => match auth::web_api(&session).await {
+                        Err(err) => {
+                            eprintln!("Error connecting to the web api, no isrc or popularity for {}: {}", uri, err);
+                            None
+                        }
+                        Ok(spotify) => Some(spotify),
+                    },
+                };
 
-                    match Track::get(&session, track_id).await {
+                for track_id in tracks {
+                    match fetch_track(&session, track_id).await {
                         Err(_) => {}
-                        Ok(track_info) => {
-                            track.track = track_info.name;
-                            for id in track_info.artists {
-                                match Artist::get(&session, id).await {
-                                    Err(_) => {}
-                                    Ok(artist) => track.artists.push(artist.name),
-                                }
+                        Ok(mut track) => {
+                            if let Some(spotify) = &spotify {
+                                fetch_web_details(spotify, &mut track);
                             }
 
-                            db.add_track(track)
+                            match &track.unavailable {
+                                None => {}
+                                Some(reason) => eprintln!("Unavailable: {} - {} ({})", track.track, track.artists.join(", "), reason),
+                            }
+
+                            db.add_track(track, &rules)
                                 .expect("error adding track to in-memory database");
                         }
                     };
@@ -277,3 +562,140 @@ pub fn populate(uris: Vec<String>, session: Arc<Mutex<Session>>, db: SpotifyData
         });
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn queue(len: usize, position: usize) -> (SpotifyDatabase, Vec<SpotifyTrack>) {
+        let db = SpotifyDatabase::new();
+        let tracks: Vec<SpotifyTrack> = (0..len)
+            .map(|i| SpotifyTrack::new(format!("track{}", i), format!("Track {}", i), vec![format!("Artist {}", i)]))
+            .collect();
+        db.write(SpotifyState {
+            queue: tracks.clone(),
+            queue_position: position,
+            history: Vec::new(),
+        });
+        return (db, tracks);
+    }
+
+    #[test]
+    fn rids_are_unique() {
+        let mut rids: Vec<u64> = (0..10000).map(|_| SpotifyTrack::new("a".to_string(), "".to_string(), Vec::new()).rid).collect();
+
+        let handles: Vec<thread::JoinHandle<Vec<u64>>> = (0..8)
+            .map(|_| {
+                thread::spawn(|| {
+                    return (0..2000)
+                        .map(|_| SpotifyTrack::new("a".to_string(), "".to_string(), Vec::new()).rid)
+                        .collect();
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            rids.extend(handle.join().unwrap());
+        }
+
+        let unique: HashSet<u64> = rids.iter().cloned().collect();
+        assert_eq!(unique.len(), rids.len());
+    }
+
+    #[test]
+    fn remove_before_current_keeps_position() {
+        let (db, tracks) = queue(5, 2);
+
+        assert_eq!(db.remove_track(tracks[0].rid).unwrap().rid, tracks[0].rid);
+        assert_eq!(db.read().unwrap().queue_position, 1);
+        assert_eq!(db.current_track().unwrap().rid, tracks[2].rid);
+        assert!(db.get_track(tracks[0].rid).is_err());
+    }
+
+    #[test]
+    fn remove_after_current_keeps_position() {
+        let (db, tracks) = queue(5, 2);
+
+        assert_eq!(db.remove_track(tracks[4].rid).unwrap().rid, tracks[4].rid);
+        assert_eq!(db.read().unwrap().queue_position, 2);
+        assert_eq!(db.current_track().unwrap().rid, tracks[2].rid);
+        assert_eq!(db.next_track().unwrap().rid, tracks[3].rid);
+        assert_eq!(db.get_track(tracks[3].rid).unwrap().rid, tracks[3].rid);
+    }
+
+    #[test]
+    fn current_track_cannot_be_removed() {
+        let (db, tracks) = queue(3, 1);
+
+        assert!(db.remove_track(tracks[1].rid).is_err());
+        assert_eq!(db.len(), 3);
+        assert_eq!(db.current_track().unwrap().rid, tracks[1].rid);
+    }
+
+    #[test]
+    fn reorder_keeps_pending_requests_in_place() {
+        let rules = SpotifmRotationCfg {
+            seed: Some(7),
+            ..SpotifmRotationCfg::default()
+        };
+
+        for mode in [ShuffleMode::Uniform, ShuffleMode::ArtistSpread, ShuffleMode::Popularity, ShuffleMode::LeastRecent] {
+            let (db, mut tracks) = queue(20, 0);
+            for idx in [3, 8, 15] {
+                tracks[idx].requester = Some("listener".to_string());
+            }
+            db.write(SpotifyState {
+                queue: tracks.clone(),
+                queue_position: 0,
+                history: Vec::new(),
+            });
+
+            let state = db.shuffle(&rules, mode).unwrap();
+            for idx in [3, 8, 15] {
+                assert_eq!(state.queue[idx].rid, tracks[idx].rid);
+            }
+            assert_eq!(state.queue[0].rid, tracks[0].rid);
+
+            // and the same seed gives the same order again
+            db.write(SpotifyState {
+                queue: tracks.clone(),
+                queue_position: 0,
+                history: Vec::new(),
+            });
+            let again = db.shuffle(&rules, mode).unwrap();
+            assert_eq!(
+                state.queue.iter().map(|x| x.rid).collect::<Vec<u64>>(),
+                again.queue.iter().map(|x| x.rid).collect::<Vec<u64>>()
+            );
+        }
+    }
+
+    #[test]
+    fn next_track_follows_the_rotation_rules() {
+        let rules = SpotifmRotationCfg {
+            artist_spacing: 1,
+            ..SpotifmRotationCfg::default()
+        };
+        let tracks: Vec<SpotifyTrack> = ["a", "b", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, artist)| SpotifyTrack::new(format!("track{}", i), format!("Track {}", i), vec![artist.to_string()]))
+            .collect();
+
+        let db = SpotifyDatabase::new();
+        db.write(SpotifyState {
+            queue: tracks.clone(),
+            queue_position: 0,
+            history: vec![tracks[0].clone()],
+        });
+
+        assert!(db.advance_track(&rules));
+        assert_eq!(db.current_track().unwrap().rid, tracks[1].rid);
+        // the second track by "b" waits, so "c" is what gets preloaded
+        assert_eq!(db.next_track().unwrap().rid, tracks[3].rid);
+
+        assert!(db.advance_track(&rules));
+        assert_eq!(db.current_track().unwrap().rid, tracks[3].rid);
+    }
+}
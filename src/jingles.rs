@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: [&str; 4] = ["wav", "ogg", "mp3", "flac"];
+
+// audio files in the jingle directory, sorted by name
+pub fn list(dir: &str) -> Result<Vec<String>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|err| format!("could not read {}: {}", dir, err))?;
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| valid_name(name))
+        .collect();
+
+    names.sort();
+    return Ok(names);
+}
+
+pub fn path(dir: &str, name: &str) -> Result<PathBuf, String> {
+    if !valid_name(name) {
+        return Err(format!("invalid jingle name: {}", name));
+    }
+    return Ok(Path::new(dir).join(name));
+}
+
+pub fn save(dir: &str, name: &str, audio: &[u8]) -> Result<(), String> {
+    let path = path(dir, name)?;
+    std::fs::create_dir_all(dir).map_err(|err| format!("could not create {}: {}", dir, err))?;
+    return std::fs::write(&path, audio).map_err(|err| format!("could not write {}: {}", name, err));
+}
+
+pub fn delete(dir: &str, name: &str) -> Result<(), String> {
+    let path = path(dir, name)?;
+    return std::fs::remove_file(&path).map_err(|err| format!("could not delete {}: {}", name, err));
+}
+
+// plain file names with a supported extension, so uploads can't escape the jingle directory
+fn valid_name(name: &str) -> bool {
+    if name.starts_with('.') || name.contains('/') || name.contains('\\') {
+        return false;
+    }
+
+    return match Path::new(name).extension().and_then(|x| x.to_str()) {
+        None => false,
+        Some(ext) => EXTENSIONS.contains(&ext.to_lowercase().as_str()),
+    };
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+// letters only found in one of the latin script languages we tell apart
+const LATIN_HINTS: [(&str, &str); 4] = [
+    ("spanish", "ñ¡¿"),
+    ("portuguese", "ãõ"),
+    ("german", "ßäö"),
+    ("french", "œèêëç"),
+];
+
+// language and script names for `text`, most specific first, e.g. ["spanish", "latin"] or ["japanese"]
+pub fn detect(text: &str) -> Vec<&'static str> {
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+
+    for c in text.chars() {
+        match script(c) {
+            None => {}
+            Some(name) => *counts.entry(name).or_insert(0) += 1,
+        }
+    }
+
+    // kanji are shared with chinese, any kana makes it japanese
+    if counts.contains_key("kana") {
+        let han = counts.remove("han").unwrap_or(0);
+        *counts.entry("kana").or_insert(0) += han;
+    }
+
+    let dominant = match counts.into_iter().max_by_key(|(_, count)| *count) {
+        None => return Vec::new(),
+        Some((name, _)) => name,
+    };
+
+    return match dominant {
+        "kana" => vec!["japanese"],
+        "han" => vec!["chinese"],
+        "hangul" => vec!["korean"],
+        "latin" => {
+            let lower = text.to_lowercase();
+            let mut names: Vec<&'static str> = LATIN_HINTS
+                .iter()
+                .filter(|(_, letters)| letters.chars().any(|x| lower.contains(x)))
+                .map(|(language, _)| *language)
+                .collect();
+            names.push("latin");
+            names
+        }
+        name => vec![name],
+    };
+}
+
+// the voice for the first of `names` found in `voices`
+pub fn voice<'a>(names: &[&str], voices: &'a HashMap<String, String>) -> Option<&'a String> {
+    return names.iter().find_map(|name| voices.get(*name));
+}
+
+fn script(c: char) -> Option<&'static str> {
+    return match c as u32 {
+        0x41..=0x5A | 0x61..=0x7A | 0xC0..=0x24F => Some("latin"),
+        0x370..=0x3FF => Some("greek"),
+        0x400..=0x4FF => Some("cyrillic"),
+        0x590..=0x5FF => Some("hebrew"),
+        0x600..=0x6FF => Some("arabic"),
+        0x900..=0x97F => Some("devanagari"),
+        0xE00..=0xE7F => Some("thai"),
+        0x3040..=0x30FF | 0xFF66..=0xFF9F => Some("kana"),
+        0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Some("hangul"),
+        0x4E00..=0x9FFF | 0x3400..=0x4DBF => Some("han"),
+        _ => None,
+    };
+}
@@ -2,75 +2,252 @@
 extern crate actix_web;
 
 use core::time;
+use std::process::exit;
 use std::sync::{Arc, Mutex};
-use std::{thread, sync::mpsc::{Receiver,SyncSender, sync_channel}};
-use librespot::core::authentication::Credentials;
-use librespot::core::config::SessionConfig;
+use std::{thread, sync::mpsc::{Receiver,SyncSender, TryRecvError, sync_channel}};
 use librespot::core::session::Session;
 use librespot::playback::audio_backend;
 use librespot::playback::config::{AudioFormat, PlayerConfig};
 use librespot::playback::mixer::NoOpVolume;
 use librespot::playback::player::{Player,PlayerEvent};
+use actix_web::dev::Server;
+use clap::Parser;
 
+mod auth;
+mod cli;
 mod db;
 mod rest;
 mod signals;
 mod config;
 mod announce;
+mod audio;
+mod jingles;
+mod language;
+mod output;
+mod schedule;
+mod shuffle;
+mod status;
+mod template;
+mod tts;
 
-use config::SpotifmConfig;
+use cli::{Cli, Command, ConfigCommand};
+use config::{OnWrap, SpotifmConfig, SpotifmRotationCfg};
+use output::{StationOutput, StationSink};
+use status::StationStatus;
 
 const BACKEND: &str = "pulseaudio";
+// how long to wait for filler tracks before starting the playlist over
+const FILLER_TIMEOUT: time::Duration = time::Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() {
 
     let mut tracks_played = 0;
+    let mut prev_track: Option<db::SpotifyTrack> = None;
+    let mut scheduler = schedule::Scheduler::new();
+    let mut prerender: Option<(u64, thread::JoinHandle<Vec<Vec<f64>>>)> = None;
+    // where to pick the current track back up after reconnecting
+    let mut resume: Option<u32> = None;
+    // unplayable tracks passed over in a row
+    let mut skipped = 0;
 
-    let args: Vec<String> = std::env::args().collect();
+    let cli = Cli::parse();
+    let config_path = cli.config_path();
+
+    env_logger::Builder::new().parse_filters(cli.log_level.as_str()).init();
+
+    match &cli.command {
+        Some(Command::Version) => {
+            println!("spotifm {}", env!("CARGO_PKG_VERSION"));
+            exit(cli::EXIT_OK);
+        }
+        Some(Command::CheckConfig) => exit(cli::check_config(config_path.as_str())),
+        Some(Command::Config { command: ConfigCommand::Convert { input, output } }) => {
+            exit(cli::convert(input.as_str(), output.as_str()));
+        }
+        Some(Command::Login { user }) => exit(cli::login(config_path.as_str(), user.clone()).await),
+        _ if cli.check_config => exit(cli::check_config(config_path.as_str())),
+        _ => {}
+    }
+
+    let config = match SpotifmConfig::read(config_path.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(cli::EXIT_CONFIG);
+        }
+        Ok(config) => Arc::new(Mutex::new(config)),
+    };
+
+    if let Some(Command::ListSources) = &cli.command {
+        exit(cli::list_sources(&config.lock().unwrap()));
+    }
+
+    let session = match create_session(&config).await {
+        Err(err) => {
+            eprintln!("Error creating session: {}", err);
+            exit(cli::EXIT_LOGIN);
+        }
+        Ok(session) => Arc::new(Mutex::new(session)),
+    };
+
+    if let Some(Command::Resolve { uri }) = &cli.command {
+        let session = session.lock().unwrap().clone();
+        exit(cli::resolve(&session, uri.as_str()).await);
+    }
+
+    if cli.dry_run {
+        let session = session.lock().unwrap().clone();
+        let config = config.lock().unwrap().clone();
+        exit(cli::dry_run(&session, &config).await);
+    }
 
     let db = db::SpotifyDatabase::new();
-    let config = Arc::new(Mutex::new(SpotifmConfig::load(args.get(1).unwrap().clone())));
-    let session = Arc::new(Mutex::new(create_session(&config).await));
+    let output = StationOutput::new();
+    let status = StationStatus::new();
     let (rest_tx, rest_rx): (SyncSender<PlayerEvent>, Receiver<PlayerEvent>) = sync_channel(100);
     let (signal_tx, signal_rx): (SyncSender<signals::SignalMessage>, Receiver<signals::SignalMessage>) = sync_channel(100);
 
     // worker threads    
-    signals::start(signal_tx.clone());
-    rest::start(rest_tx.clone(), config.clone(), session.clone(), db.clone());
-    db::populate(config.lock().unwrap().uris.clone(), session.clone(), db.clone());
+    signals::start(signal_tx.clone(), config.clone());
+    signals::watch(config.clone(), signal_tx.clone());
+    let server = match cli.no_rest {
+        true => None,
+        false => match rest::start(cli.rest_listen.clone(), rest_tx.clone(), config.clone(), session.clone(), db.clone(), output.clone(), status.clone()) {
+            Err(err) => {
+                eprintln!("Error starting the rest api: {}", err);
+                exit(cli::EXIT_REST);
+            }
+            Ok(server) => Some(server),
+        },
+    };
+    db::populate(config.lock().unwrap().uris.clone(), config.lock().unwrap().rotation.clone(), session.clone(), db.clone());
     
     eprintln!("Waiting for playlist...");
 
     // wait until at least one track in playlist
     while db.len() == 0 {
         thread::sleep(time::Duration::from_millis(10));
+        while rest_rx.try_recv().is_ok() {}
+        idle_signals(&config, &session, &db, &output, &status, server.as_ref(), &signal_rx).await;
     }
 
     eprintln!("Playlist partially loaded, starting playback...");
 
     'track_list: loop {
 
-        db.advance_track();
+        let rotation = config.lock().unwrap().rotation.clone();
+        let resume_ms = resume.take();
+
+        if resume_ms.is_none() && !db.advance_track(&rotation) {
+            if rotation.on_wrap == OnWrap::Filler {
+                eprintln!("End of playlist, loading filler...");
+                // filler that is still queued from an earlier wrap isn't appended again
+                let filler_rules = SpotifmRotationCfg { dedupe: true, ..rotation.clone() };
+                let loading = db::populate(rotation.filler.clone(), filler_rules, session.clone(), db.clone());
+                let started = std::time::Instant::now();
+
+                while !db.has_next() && !loading.is_finished() && started.elapsed() < FILLER_TIMEOUT {
+                    thread::sleep(time::Duration::from_millis(100));
+                    // nothing is playing, a skip or play would otherwise stop the track that starts next
+                    while rest_rx.try_recv().is_ok() {}
+                    idle_signals(&config, &session, &db, &output, &status, server.as_ref(), &signal_rx).await;
+                }
+
+                if db.has_next() {
+                    continue 'track_list;
+                }
+
+                eprintln!("Error loading filler, no new tracks, repeating the playlist");
+                let repeat_rules = SpotifmRotationCfg { on_wrap: OnWrap::Repeat, ..rotation.clone() };
+                db.advance_track(&repeat_rules);
+            } else {
+                eprintln!("End of playlist, waiting for new tracks...");
+
+                while !db.has_next() {
+                    thread::sleep(time::Duration::from_millis(100));
+                    // nothing is playing, a skip or play would otherwise stop the track that starts next
+                    while rest_rx.try_recv().is_ok() {}
+                    idle_signals(&config, &session, &db, &output, &status, server.as_ref(), &signal_rx).await;
+                }
+
+                continue 'track_list;
+            }
+        }
 
         match db.current_track() {
             Err(err) => panic!("{}", err.unwrap()),
             Ok(track) => {
-                tracks_played += 1;
+                if let Some(reason) = &track.unavailable {
+                    eprintln!("Skipping unavailable: {} - {} ({})", track.track, track.artists.join(", "), reason);
+                    skipped += 1;
+
+                    // nothing in the queue can be played, give the rest api a chance to add something
+                    if skipped >= db.len() {
+                        eprintln!("No playable tracks in the queue, waiting...");
+                        thread::sleep(time::Duration::from_secs(10));
+                        skipped = 0;
+                        let _ = db.clear_unavailable(db::LOAD_FAILED);
+                    }
 
-                eprintln!("Playing: {} - {}", track.track, track.artists.join(", "));
+                    continue 'track_list;
+                }
+
+                skipped = 0;
+
+                match resume_ms {
+                    Some(position_ms) => eprintln!("Resuming: {} - {} at {}s", track.track, track.artists.join(", "), position_ms / 1000),
+                    None => {
+                        tracks_played += 1;
+                        eprintln!("Playing: {} - {}", track.track, track.artists.join(", "));
+                    }
+                }
 
+                let sink_output = output.clone();
                 let (mut player, mut player_rx) = Player::new(PlayerConfig::default(), session.lock().unwrap().clone(), Box::new(NoOpVolume), move || {
-                    audio_backend::find(Some(BACKEND.to_string())).unwrap()(None, AudioFormat::default())
+                    let sink = audio_backend::find(Some(BACKEND.to_string())).unwrap()(None, AudioFormat::default());
+                    Box::new(StationSink::new(sink, sink_output))
                 });
 
-                announce::announcements(config.clone(), &track, tracks_played);
+                if let Some(position_ms) = resume_ms {
+                    // a resumed track was already announced
+                    player.load(track.spotify_id(), true, position_ms);
+                    output.started(position_ms);
+                } else {
+                    // with talk-over the track starts right away and the announcement plays over its intro
+                    let talkover = config.lock().unwrap().announce.talkover.enable;
 
-                player.load(track.spotify_id(), true, 0);
-        
+                    if talkover {
+                        player.load(track.spotify_id(), true, 0);
+                        output.started(0);
+                    }
+
+                    // skip and play shout-outs from the rest api go first, then the bumper
+                    let mut clips = output.take_cued();
+                    clips.extend(announce::bumper(config.clone(), &track, prev_track.as_ref(), tracks_played));
+                    clips.extend(match prerender.take() {
+                        Some((rid, handle)) if rid == track.rid => handle.join().unwrap_or_default(),
+                        _ => announce::prepare(config.clone(), &track, prev_track.as_ref()),
+                    });
+
+                    announce::announcements(config.clone(), &track, prev_track.as_ref(), clips, &mut scheduler, &output);
+
+                    if !talkover {
+                        player.load(track.spotify_id(), true, 0);
+                        output.started(0);
+                    }
+
+                    prev_track = Some(track.clone());
+                }
+
+                // the next track's announcement is rendered while this one plays
                 match db.next_track() {
                     Err(err) => eprintln!("Preload error: {}", err),
-                    Ok(track) => player.preload(track.spotify_id()),
+                    Ok(next) => {
+                        player.preload(next.spotify_id());
+                        if prerender.is_none() {
+                            prerender = Some(announce::prerender(config.clone(), next, track.clone()));
+                        }
+                    }
                 }
                 
                 loop {
@@ -80,12 +257,38 @@ async fn main() {
                     let player_event = player_rx.try_recv();
                     let signal_event = signal_rx.try_recv();
 
+                    let stall_secs = config.lock().unwrap().reconnect.stall_secs;
+
+                    let lost = if session.lock().unwrap().is_invalid() {
+                        Some("session closed".to_string())
+                    } else if matches!(player_event, Err(TryRecvError::Disconnected)) {
+                        Some("player stopped".to_string())
+                    } else if output.idle().as_secs() >= stall_secs {
+                        Some(format!("no audio for {}s", stall_secs))
+                    } else if matches!(signal_event, Ok(signals::SignalMessage::SessionExpired)) {
+                        Some("SIGALRM".to_string())
+                    } else {
+                        None
+                    };
+
+                    if let Some(reason) = lost {
+                        eprintln!("Connection lost ({}), reconnecting...", reason);
+                        resume = Some(output.position_ms());
+                        player.stop();
+                        reconnect(&config, &session, &status, reason).await;
+                        // tracks that failed to load may have failed because of the lost connection
+                        let _ = db.clear_unavailable(db::LOAD_FAILED);
+                        continue 'track_list;
+                    }
+
                     if !signal_event.is_err() {
                         match signal_event.unwrap() {
-                            signals::SignalMessage::SessionExpired => {
-                                eprintln!("Session expired, creating new session...");
-                                *session.lock().unwrap() = create_session(&config).await;
-                                continue 'track_list;
+                            signals::SignalMessage::SessionExpired => {}
+                            signals::SignalMessage::Reload => {
+                                reload_config(&config, &session, &db);
+                            }
+                            signals::SignalMessage::Shutdown => {
+                                shutdown(&config, &session, &output, server.as_ref(), Some(&mut player)).await;
                             }
                         }
                     }
@@ -108,9 +311,28 @@ async fn main() {
                             PlayerEvent::Stopped { .. } => {
                                 player.stop();
                                 continue 'track_list;
+                            },
+                            // librespot couldn't load the track, or the one preloaded after it
+                            PlayerEvent::Unavailable { track_id, .. } => {
+                                let id = track_id.to_base62().unwrap();
+                                eprintln!("Track {} could not be loaded, marking it unavailable", id);
+                                match db.mark_unavailable(id.as_str(), db::LOAD_FAILED) {
+                                    Err(err) => eprintln!("Error marking {} unavailable: {}", id, err),
+                                    Ok(_) => {}
+                                }
+                                if id == track.id {
+                                    player.stop();
+                                    continue 'track_list;
+                                }
                             },   
                             PlayerEvent::Changed { new_track_id, .. } => {
                                 player.preload(new_track_id);
+                                match db.next_track() {
+                                    Err(_) => {}
+                                    Ok(next) => {
+                                        prerender = Some(announce::prerender(config.clone(), next, track.clone()));
+                                    }
+                                }
                             },
                             _ => {}    
                         }
@@ -123,16 +345,138 @@ async fn main() {
 
 }
 
-pub async fn create_session(config: &Arc<Mutex<SpotifmConfig>>) -> Session {
-    let config = config.lock().unwrap();
-    let session_config = SessionConfig::default();
-    let credentials = Credentials::with_password(config.user.clone(), config.pass.clone());
+// handles signals while nothing is playing
+pub async fn idle_signals(
+    config: &Arc<Mutex<SpotifmConfig>>,
+    session: &Arc<Mutex<Session>>,
+    db: &db::SpotifyDatabase,
+    output: &StationOutput,
+    status: &StationStatus,
+    server: Option<&Server>,
+    signal_rx: &Receiver<signals::SignalMessage>,
+) {
+    match signal_rx.try_recv() {
+        Err(_) => {}
+        Ok(signals::SignalMessage::SessionExpired) => {
+            reconnect(config, session, status, "SIGALRM".to_string()).await;
+            let _ = db.clear_unavailable(db::LOAD_FAILED);
+        }
+        Ok(signals::SignalMessage::Reload) => {
+            reload_config(config, session, db);
+        }
+        Ok(signals::SignalMessage::Shutdown) => {
+            shutdown(config, session, output, server, None).await;
+        }
+    }
+}
+
+// drains the rest api, fades the station out, saves the config and logs out, then exits
+pub async fn shutdown(
+    config: &Arc<Mutex<SpotifmConfig>>,
+    session: &Arc<Mutex<Session>>,
+    output: &StationOutput,
+    server: Option<&Server>,
+    player: Option<&mut Player>,
+) {
+    eprintln!("Shutting down...");
+
+    // requests already being handled finish, new ones are refused
+    if let Some(server) = server {
+        server.stop(true).await;
+    }
+
+    let config = config.lock().unwrap().clone();
+
+    if let Some(player) = player {
+        if config.shutdown.fade_ms > 0 {
+            output.fade_out(config.shutdown.fade_ms);
+            thread::sleep(time::Duration::from_millis(config.shutdown.fade_ms as u64));
+        }
+        player.stop();
+    }
+
+    if config.persist {
+        match config.save() {
+            Err(err) => eprintln!("Error saving config: {}", err),
+            Ok(_) => {}
+        }
+    }
+
+    session.lock().unwrap().shutdown();
+
+    eprintln!("Bye");
+    exit(cli::EXIT_OK);
+}
+
+// replaces the session, retrying with exponential backoff until it works
+pub async fn reconnect(config: &Arc<Mutex<SpotifmConfig>>, session: &Arc<Mutex<Session>>, status: &StationStatus, reason: String) {
+    status.disconnected(reason.as_str());
+
+    let reconnect = config.lock().unwrap().reconnect.clone();
+    let mut delay = time::Duration::from_millis(reconnect.backoff_min_ms);
+    let max_delay = time::Duration::from_secs(reconnect.backoff_max_secs);
+
+    loop {
+        match create_session(config).await {
+            Ok(new_session) => {
+                *session.lock().unwrap() = new_session;
+                status.reconnected();
+                eprintln!("Reconnected");
+                return;
+            }
+            Err(err) => {
+                status.failed(err.as_str());
+                eprintln!("Error creating session: {}, retrying in {}s", err, delay.as_secs_f32());
+            }
+        }
+
+        thread::sleep(delay);
+        delay = (delay * 2).min(max_delay);
+    }
+}
+
+// re-reads the config file and applies it to the running station, a broken file is logged and ignored
+pub fn reload_config(config: &Arc<Mutex<SpotifmConfig>>, session: &Arc<Mutex<Session>>, db: &db::SpotifyDatabase) {
+    let path = config.lock().unwrap().path.clone();
+
+    let new = match SpotifmConfig::read(path.as_str()) {
+        Err(err) => {
+            eprintln!("Not reloading config: {}", err);
+            return;
+        }
+        Ok(new) => new,
+    };
+
+    let old_uris = config.lock().unwrap().uris.clone();
+    let changed = config.lock().unwrap().apply(new);
+
+    let _ = db.clear_unavailable(db::LOAD_FAILED);
+
+    if changed.len() == 0 {
+        eprintln!("Reloaded config, nothing changed");
+        return;
+    }
+
+    eprintln!("Reloaded config, changed: {}", changed.join(", "));
+
+    // tracks from new sources join the queue, removed sources are only dropped on restart
+    if changed.contains(&"uris") {
+        let (uris, rotation) = {
+            let config = config.lock().unwrap();
+            (config.uris.clone(), config.rotation.clone())
+        };
+        let added: Vec<String> = uris.into_iter().filter(|uri| !old_uris.contains(uri)).collect();
+        if added.len() > 0 {
+            db::populate(added, rotation, session.clone(), db.clone());
+        }
+    }
+}
 
-    let (session, _) = Session::connect(session_config, credentials, None, false).await
-        .map_err(|err| { eprintln!("Error creating session: {}", err.to_string())} )
-        .unwrap();
+pub async fn create_session(config: &Arc<Mutex<SpotifmConfig>>) -> Result<Session, String> {
+    let config = config.lock().unwrap().clone();
+    let (session, _) = auth::login(&config).await?;
 
-    return session;
+    return Ok(session);
 }
 
 
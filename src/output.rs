@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use librespot::playback::audio_backend::{Sink, SinkResult};
+use librespot::playback::convert::Converter;
+use librespot::playback::decoder::AudioPacket;
+use librespot::playback::{NUM_CHANNELS, SAMPLE_RATE};
+
+use crate::config::SpotifmTalkoverCfg;
+
+// samples handed to the backend per write when playing announcements
+const CHUNK_LEN: usize = 4096;
+
+// announcements waiting to be played through the station output
+#[derive(Clone)]
+pub struct StationOutput {
+    pending: Arc<Mutex<VecDeque<Vec<f64>>>>,
+    // event announcements, still rendering, held back until the next track is announced
+    cued: Arc<Mutex<Vec<JoinHandle<Option<Vec<f64>>>>>>,
+    overlay: Arc<Mutex<Overlay>>,
+    // music frames written since the track started, and when the backend was last written to
+    frames: Arc<AtomicU64>,
+    last_write: Arc<Mutex<Instant>>,
+}
+
+// an announcement spoken over the music, which is ducked while it plays
+struct Overlay {
+    samples: VecDeque<f64>,
+    gain: f64,
+    duck: f64,
+    ramp_down: f64,
+    ramp_up: f64,
+    // fading the station out on shutdown
+    fade: f64,
+    fade_step: f64,
+}
+
+// wraps the audio backend so announcements go out through the same path as the music
+pub struct StationSink {
+    sink: Box<dyn Sink>,
+    output: StationOutput,
+}
+
+impl StationOutput {
+    pub fn new() -> StationOutput {
+        return StationOutput {
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+            cued: Arc::new(Mutex::new(Vec::new())),
+            overlay: Arc::new(Mutex::new(Overlay {
+                samples: VecDeque::new(),
+                gain: 1.0,
+                duck: 1.0,
+                ramp_down: 1.0,
+                ramp_up: 1.0,
+                fade: 1.0,
+                fade_step: 0.0,
+            })),
+            frames: Arc::new(AtomicU64::new(0)),
+            last_write: Arc::new(Mutex::new(Instant::now())),
+        };
+    }
+
+    // a track was loaded at `position_ms`
+    pub fn started(&self, position_ms: u32) {
+        self.frames.store(position_ms as u64 * SAMPLE_RATE as u64 / 1000, Ordering::Relaxed);
+        *self.last_write.lock().unwrap() = Instant::now();
+    }
+
+    // how far into the current track the music has played
+    pub fn position_ms(&self) -> u32 {
+        return (self.frames.load(Ordering::Relaxed) * 1000 / SAMPLE_RATE as u64) as u32;
+    }
+
+    // how long since anything was written to the backend
+    pub fn idle(&self) -> Duration {
+        return self.last_write.lock().unwrap().elapsed();
+    }
+
+    fn written(&self, music_frames: usize) {
+        self.frames.fetch_add(music_frames as u64, Ordering::Relaxed);
+        *self.last_write.lock().unwrap() = Instant::now();
+    }
+
+    // queues decoded samples to be played before the next music packet
+    pub fn queue(&self, samples: Vec<f64>) {
+        self.pending.lock().unwrap().push_back(samples);
+    }
+
+    // holds an announcement being rendered back until `take_cued`, so it isn't played into the track being replaced
+    pub fn cue(&self, render: JoinHandle<Option<Vec<f64>>>) {
+        self.cued.lock().unwrap().push(render);
+    }
+
+    // waits for the cued announcements to finish rendering
+    pub fn take_cued(&self) -> Vec<Vec<f64>> {
+        let renders: Vec<JoinHandle<Option<Vec<f64>>>> = self.cued.lock().unwrap().drain(..).collect();
+        return renders.into_iter().filter_map(|x| x.join().ok().flatten()).collect();
+    }
+
+    // queues decoded samples to be mixed over the music, ducking it as configured
+    pub fn talk_over(&self, samples: Vec<f64>, config: &SpotifmTalkoverCfg) {
+        let mut overlay = self.overlay.lock().unwrap();
+        overlay.duck = 10f64.powf(-config.duck_db.abs() as f64 / 20.0);
+        overlay.ramp_down = (1.0 - overlay.duck) / ramp_frames(config.ramp_down_ms);
+        overlay.ramp_up = (1.0 - overlay.duck) / ramp_frames(config.ramp_up_ms);
+        overlay.samples.extend(samples);
+    }
+
+    // fades everything out over `ms` milliseconds, there is no fading back in
+    pub fn fade_out(&self, ms: u32) {
+        self.overlay.lock().unwrap().fade_step = 1.0 / ramp_frames(ms);
+    }
+
+    fn take(&self) -> Vec<Vec<f64>> {
+        return self.pending.lock().unwrap().drain(..).collect();
+    }
+
+    fn mix(&self, music: &mut [f64]) {
+        let mut overlay = self.overlay.lock().unwrap();
+
+        if overlay.samples.is_empty() && overlay.gain >= 1.0 && overlay.fade_step == 0.0 {
+            return;
+        }
+
+        for frame in music.chunks_mut(NUM_CHANNELS as usize) {
+            if overlay.samples.is_empty() {
+                overlay.gain = (overlay.gain + overlay.ramp_up).min(1.0);
+            } else {
+                overlay.gain = (overlay.gain - overlay.ramp_down).max(overlay.duck);
+            }
+
+            // the announcement starts once the music is fully ducked
+            let speaking = !overlay.samples.is_empty() && overlay.gain <= overlay.duck;
+
+            for sample in frame.iter_mut() {
+                *sample *= overlay.gain;
+                if speaking {
+                    *sample = (*sample + overlay.samples.pop_front().unwrap_or(0.0)).clamp(-1.0, 1.0);
+                }
+                *sample *= overlay.fade;
+            }
+
+            overlay.fade = (overlay.fade - overlay.fade_step).max(0.0);
+        }
+    }
+}
+
+fn ramp_frames(ms: u32) -> f64 {
+    return (ms as f64 * SAMPLE_RATE as f64 / 1000.0).max(1.0);
+}
+
+impl StationSink {
+    pub fn new(sink: Box<dyn Sink>, output: StationOutput) -> StationSink {
+        return StationSink { sink: sink, output: output };
+    }
+}
+
+impl Sink for StationSink {
+    fn start(&mut self) -> SinkResult<()> {
+        return self.sink.start();
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        return self.sink.stop();
+    }
+
+    fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()> {
+        for samples in self.output.take() {
+            for chunk in samples.chunks(CHUNK_LEN) {
+                self.sink.write(AudioPacket::Samples(chunk.to_vec()), converter)?;
+                self.output.written(0);
+            }
+        }
+
+        return match packet {
+            AudioPacket::Samples(mut samples) => {
+                let frames = samples.len() / NUM_CHANNELS as usize;
+                self.output.mix(&mut samples);
+                self.sink.write(AudioPacket::Samples(samples), converter)?;
+                self.output.written(frames);
+                Ok(())
+            }
+            packet => self.sink.write(packet, converter),
+        };
+    }
+}
@@ -1,35 +1,38 @@
 use serde::{Deserialize, Serialize};
 
 use actix_web::{
+    dev::Server,
     middleware, rt,
-    web::{self, Data, Path, Form},
+    web::{self, Bytes, Data, Path, Form, Json, PayloadConfig},
     App, HttpRequest, HttpResponse, HttpServer,
 };
 use std::sync::{Arc, Mutex};
-use chrono::{Duration as ChronoDuration, Utc};
-use librespot::core::{keymaster, session::Session, spotify_id::SpotifyId};
+use librespot::core::{session::Session, spotify_id::SpotifyId};
 use librespot::playback::player::PlayerEvent;
 use rspotify::{
-    model::{SearchResult, SearchType, TrackId},
+    model::{FullTrack, SearchResult, SearchType, TrackId},
     prelude::*,
     AuthCodeSpotify,
 };
 use std::collections::HashMap;
-use std::collections::HashSet;
-use std::iter::FromIterator;
-use std::{sync::mpsc::SyncSender, thread};
+use std::{sync::mpsc::{channel, SyncSender}, thread};
 
-use crate::db::{SpotifyDatabase, SpotifyTrack};
-use crate::config::SpotifmConfig;
-use crate::announce::{espeak, get_elevenlabs_tts, play_elevenlabs};
+use crate::db::{fetch_track, resolve_uri, SpotifyDatabase, SpotifyTrack};
+use crate::config::{ShuffleMode, SpotifmConfig, TtsKind};
+use crate::announce::{self, say, AnnounceEvent};
+use crate::auth;
+use crate::jingles;
+use crate::output::StationOutput;
+use crate::status::StationStatus;
+use crate::tts::{self, ElevenLabs, Espeak};
 
-const CLIENT_ID: &str = "65b708073fc0480ea92a077233ca87bd";
-const SCOPES: &str =
-    "streaming,user-read-playback-state,user-modify-playback-state,user-read-currently-playing";
+// largest jingle upload accepted
+const MAX_UPLOAD: usize = 32 * 1024 * 1024;
 
 #[derive(Serialize, Deserialize)]
 pub struct AnnounceBumper {
     pub enable: Option<bool>,
+    pub engine: Option<String>,
     pub tag: Option<String>,
     pub freq: Option<usize>,
     pub speed: Option<u32>,
@@ -42,6 +45,7 @@ pub struct AnnounceBumper {
 #[derive(Serialize, Deserialize)]
 pub struct AnnounceSong {
     pub enable: Option<bool>,
+    pub engine: Option<String>,
     pub speed: Option<u32>,
     pub amplitude: Option<u32>,
     pub voice: Option<String>,
@@ -49,29 +53,85 @@ pub struct AnnounceSong {
     pub gap: Option<u32>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct QueueUris {
+    pub uris: Vec<String>,
+    pub position: Option<String>,
+    pub requester: Option<String>,
+    pub via: Option<String>,
+}
+
 #[get("/elevenlabs")]
 pub async fn do_elevenlabs_say(
     req: HttpRequest,
     config: Data<Arc<Mutex<SpotifmConfig>>>,
+    output: Data<StationOutput>,
 ) -> HttpResponse {
     let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
 
-    let text = query.get("text").unwrap().clone();
+    let text = match query.get("text") {
+        None => return HttpResponse::BadRequest().json(HashMap::from([("error", "missing text")])),
+        Some(text) => text.clone(),
+    };
 
-    get_elevenlabs_tts(text.as_str(), config.lock().unwrap().elevenlabs.clone());
-    play_elevenlabs();
+    let engine = ElevenLabs { config: config.lock().unwrap().elevenlabs.clone() };
 
-    return HttpResponse::Ok().json(HashMap::from([("text", text)]));
+    return match say(text.clone(), &engine, &output) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(_) => HttpResponse::Ok().json(HashMap::from([("text", text)])),
+    };
 }
 
 #[get("/espeak")]
 pub async fn do_espeak_say(
     req: HttpRequest,
     config: Data<Arc<Mutex<SpotifmConfig>>>,
+    output: Data<StationOutput>,
 ) -> HttpResponse {
     let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
-    espeak(query.get("text").unwrap().clone(), config.lock().unwrap().announce.song.espeak.clone());
-    return HttpResponse::Ok().json(HashMap::from([("text", query.get("text").unwrap())]));
+
+    let text = match query.get("text") {
+        None => return HttpResponse::BadRequest().json(HashMap::from([("error", "missing text")])),
+        Some(text) => text.clone(),
+    };
+
+    let engine = Espeak {
+        binary: "espeak".to_string(),
+        config: config.lock().unwrap().announce.song.espeak.clone(),
+    };
+    return match say(text.clone(), &engine, &output) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(_) => HttpResponse::Ok().json(HashMap::from([("text", text)])),
+    };
+}
+
+#[get("/say/{type}")]
+pub async fn do_say(
+    req: HttpRequest,
+    path: Path<String>,
+    config: Data<Arc<Mutex<SpotifmConfig>>>,
+    output: Data<StationOutput>,
+) -> HttpResponse {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+
+    let text = match query.get("text") {
+        None => return HttpResponse::BadRequest().json(HashMap::from([("error", "missing text")])),
+        Some(text) => text.clone(),
+    };
+
+    let engine = {
+        let config = config.lock().unwrap();
+        match path.0.as_str() {
+            "bumper" => tts::engine(config.announce.bumper.engine, &config.announce.bumper.espeak, &config),
+            "song" => tts::engine(config.announce.song.engine, &config.announce.song.espeak, &config),
+            _ => return HttpResponse::NotFound().finish(),
+        }
+    };
+
+    return match say(text.clone(), engine.as_ref(), &output) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(_) => HttpResponse::Ok().json(HashMap::from([("text", text)])),
+    };
 }
 
 #[delete("/announce/bumper/tags")]
@@ -79,6 +139,7 @@ pub async fn delete_announce_bumper_tags(
     config: Data<Arc<Mutex<SpotifmConfig>>>,
 ) -> HttpResponse {
     config.lock().unwrap().announce.bumper.clear_tags();
+    persist(&config.lock().unwrap());
     return HttpResponse::Ok().json(config.lock().unwrap().announce.bumper.clone());
 }
 
@@ -91,6 +152,13 @@ pub async fn edit_announce_song(
         config.lock().unwrap().announce.song.enable = form.enable.unwrap();
     }
 
+    if form.engine.is_some() {
+        match TtsKind::from_name(form.engine.as_ref().unwrap()) {
+            None => return HttpResponse::BadRequest().json(HashMap::from([("error", "unknown engine")])),
+            Some(engine) => config.lock().unwrap().announce.song.engine = engine,
+        }
+    }
+
     if form.speed.is_some() {
         config.lock().unwrap().announce.song.espeak.speed = form.speed.unwrap();
     }
@@ -111,6 +179,7 @@ pub async fn edit_announce_song(
         config.lock().unwrap().announce.song.espeak.gap = form.gap.unwrap();
     }    
 
+    persist(&config.lock().unwrap());
     return HttpResponse::Ok().json(config.lock().unwrap().announce.song.clone());
 }
 
@@ -123,6 +192,13 @@ pub async fn edit_announce_bumper(
         config.lock().unwrap().announce.bumper.enable = form.enable.unwrap();
     }
 
+    if form.engine.is_some() {
+        match TtsKind::from_name(form.engine.as_ref().unwrap()) {
+            None => return HttpResponse::BadRequest().json(HashMap::from([("error", "unknown engine")])),
+            Some(engine) => config.lock().unwrap().announce.bumper.engine = engine,
+        }
+    }
+
     if form.tag.is_some() {
         config.lock().unwrap().announce.bumper.add_tag(form.tag.clone().unwrap());
     }
@@ -151,6 +227,7 @@ pub async fn edit_announce_bumper(
         config.lock().unwrap().announce.bumper.espeak.gap = form.gap.unwrap();
     }    
 
+    persist(&config.lock().unwrap());
     return HttpResponse::Ok().json(config.lock().unwrap().announce.bumper.clone());
 }
 
@@ -166,6 +243,78 @@ pub async fn get_announce(
     }
 }
 
+#[get("/config")]
+pub async fn get_config(config: Data<Arc<Mutex<SpotifmConfig>>>) -> HttpResponse {
+    return HttpResponse::Ok().json(config.lock().unwrap().redacted());
+}
+
+#[post("/config/save")]
+pub async fn save_config(config: Data<Arc<Mutex<SpotifmConfig>>>) -> HttpResponse {
+    let config = config.lock().unwrap();
+
+    return match config.save() {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(_) => HttpResponse::Ok().json(HashMap::from([("saved", config.path.clone())])),
+    };
+}
+
+// saves runtime edits when `persist` is on
+fn persist(config: &SpotifmConfig) {
+    if !config.persist {
+        return;
+    }
+
+    match config.save() {
+        Err(err) => eprintln!("Error saving config: {}", err),
+        Ok(_) => {}
+    }
+}
+
+#[get("/jingles")]
+pub async fn list_jingles(config: Data<Arc<Mutex<SpotifmConfig>>>) -> HttpResponse {
+    let jingles_cfg = config.lock().unwrap().announce.bumper.jingles.clone();
+
+    return match jingles::list(jingles_cfg.dir.as_str()) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(names) => HttpResponse::Ok().json(
+            names
+                .iter()
+                .map(|name| (name.clone(), jingles_cfg.weight(name)))
+                .collect::<HashMap<String, u32>>(),
+        ),
+    };
+}
+
+#[post("/jingles/{name}")]
+pub async fn upload_jingle(
+    path: Path<String>,
+    body: Bytes,
+    config: Data<Arc<Mutex<SpotifmConfig>>>,
+) -> HttpResponse {
+    let dir = config.lock().unwrap().announce.bumper.jingles.dir.clone();
+
+    // make sure it's something we can actually play
+    match crate::audio::decode(body.to_vec()) {
+        Err(err) => return HttpResponse::BadRequest().json(HashMap::from([("error", err)])),
+        Ok(_) => {}
+    }
+
+    return match jingles::save(dir.as_str(), path.0.as_str(), &body) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(_) => HttpResponse::Ok().json(HashMap::from([("name", path.0.clone())])),
+    };
+}
+
+#[delete("/jingles/{name}")]
+pub async fn delete_jingle(path: Path<String>, config: Data<Arc<Mutex<SpotifmConfig>>>) -> HttpResponse {
+    let dir = config.lock().unwrap().announce.bumper.jingles.dir.clone();
+
+    return match jingles::delete(dir.as_str(), path.0.as_str()) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(_) => HttpResponse::Ok().json(HashMap::from([("name", path.0.clone())])),
+    };
+}
+
 #[get("/search/{type}/{num}")]
 pub async fn search(
     req: HttpRequest,
@@ -218,6 +367,13 @@ pub async fn np(db: Data<SpotifyDatabase>) -> HttpResponse {
     };
 }
 
+#[get("/status")]
+pub async fn get_status(status: Data<StationStatus>, output: Data<StationOutput>) -> HttpResponse {
+    let mut info = serde_json::to_value(status.info()).unwrap();
+    info["position_ms"] = output.position_ms().into();
+    return HttpResponse::Ok().json(info);
+}
+
 #[get("/prev")]
 pub async fn prev_track(db: Data<SpotifyDatabase>) -> HttpResponse {
     return match db.prev_track() {
@@ -235,8 +391,28 @@ pub async fn next_track(db: Data<SpotifyDatabase>) -> HttpResponse {
 }
 
 #[get("/skip")]
-pub async fn skip(data: Data<SyncSender<PlayerEvent>>, db: Data<SpotifyDatabase>) -> HttpResponse {
+pub async fn skip(
+    req: HttpRequest,
+    data: Data<SyncSender<PlayerEvent>>,
+    config: Data<Arc<Mutex<SpotifmConfig>>>,
+    db: Data<SpotifyDatabase>,
+    output: Data<StationOutput>,
+) -> HttpResponse {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
     let next_playing = db.next_track().unwrap();
+
+    match db.current_track() {
+        Err(_) => {}
+        Ok(mut now_playing) => {
+            // the template's requester is whoever skipped
+            now_playing.requester = query.get("requester").cloned();
+            now_playing.via = query.get("via").cloned();
+            // rendered in the background and played once the player stopped, before the next track,
+            // cued before stopping so the main loop can't start the next track without it
+            output.cue(announce::event(config.get_ref().clone(), AnnounceEvent::Skip, now_playing));
+        }
+    }
+
     return match data.send(PlayerEvent::Stopped {
         play_request_id: 0,
         track_id: SpotifyId::from_base62("0").unwrap(),
@@ -249,8 +425,26 @@ pub async fn skip(data: Data<SyncSender<PlayerEvent>>, db: Data<SpotifyDatabase>
 }
 
 #[get("/shuffle")]
-pub async fn shuffle(db: Data<SpotifyDatabase>) -> HttpResponse {
-    return match db.shuffle() {
+pub async fn shuffle(
+    req: HttpRequest,
+    config: Data<Arc<Mutex<SpotifmConfig>>>,
+    db: Data<SpotifyDatabase>,
+) -> HttpResponse {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let rotation = config.lock().unwrap().rotation.clone();
+
+    let mode = match query.get("mode") {
+        None => rotation.shuffle,
+        Some(name) => match ShuffleMode::from_name(name) {
+            None => {
+                return HttpResponse::BadRequest()
+                    .json(HashMap::from([("error", format!("unknown shuffle mode: {}", name))]))
+            }
+            Some(mode) => mode,
+        },
+    };
+
+    return match db.shuffle(&rotation, mode) {
         Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err.to_string())])),
         Ok(state) => HttpResponse::Ok().json(state.queue),
     };
@@ -258,11 +452,13 @@ pub async fn shuffle(db: Data<SpotifyDatabase>) -> HttpResponse {
 
 #[get("/queue/{id}")]
 pub async fn queue(
+    req: HttpRequest,
     path: Path<String>,
     data: Data<SyncSender<PlayerEvent>>,
     session: Data<Arc<Mutex<Session>>>,
     db: Data<SpotifyDatabase>,
 ) -> HttpResponse {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
     let now_playing = db.current_track().unwrap();
     let next_playing = db.next_track().unwrap();
 
@@ -278,19 +474,9 @@ pub async fn queue(
             return match spotify.track(TrackId::from_id(path.0).unwrap()) {
                 Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err.to_string())])),
                 Ok(track) => {
-                    let spotify_track = SpotifyTrack::new(
-                        track
-                            .id
-                            .unwrap()
-                            .to_string()
-                            .split(":")
-                            .collect::<Vec<&str>>()
-                            .get(2)
-                            .unwrap()
-                            .to_string(),
-                        track.name,
-                        track.artists.iter().map(|x| x.clone().name).collect(),
-                    );
+                    let mut spotify_track = from_full_track(track);
+                    spotify_track.requester = query.get("requester").cloned();
+                    spotify_track.via = query.get("via").cloned();
                     return match db.queue_track(spotify_track.clone()) {
                         Err(err) => {
                             HttpResponse::Ok().json(HashMap::from([("error", err.to_string())]))
@@ -314,13 +500,77 @@ pub async fn queue(
     };
 }
 
+#[post("/queue")]
+pub async fn queue_uris(
+    body: Json<QueueUris>,
+    data: Data<SyncSender<PlayerEvent>>,
+    session: Data<Arc<Mutex<Session>>>,
+    db: Data<SpotifyDatabase>,
+) -> HttpResponse {
+    let next = match body.position.as_deref() {
+        None | Some("next") => true,
+        Some("end") => false,
+        Some(position) => {
+            return HttpResponse::BadRequest()
+                .json(HashMap::from([("error", format!("unknown position: {}", position))]))
+        }
+    };
+
+    let session = session.lock().unwrap().clone();
+    let mut tracks: Vec<SpotifyTrack> = Vec::new();
+
+    for uri in body.uris.iter() {
+        let track_ids = match resolve_uri(&session, uri.as_str()).await {
+            Err(err) => return HttpResponse::Ok().json(HashMap::from([("error", err)])),
+            Ok(track_ids) => track_ids,
+        };
+
+        for track_id in track_ids {
+            match fetch_track(&session, track_id).await {
+                Err(err) => eprintln!("Error queueing {}: {}", uri, err),
+                Ok(mut track) => {
+                    track.requester = body.requester.clone();
+                    track.via = body.via.clone();
+                    tracks.push(track);
+                }
+            }
+        }
+    }
+
+    if tracks.len() == 0 {
+        return HttpResponse::Ok().json(HashMap::from([("error", "no playable tracks found")]));
+    }
+
+    return match db.insert_tracks(tracks, next) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(inserted) => {
+            if next {
+                match db.current_track() {
+                    Err(_) => {}
+                    Ok(now_playing) => {
+                        let _ = data.send(PlayerEvent::Changed {
+                            old_track_id: now_playing.spotify_id(),
+                            new_track_id: inserted[0].spotify_id(),
+                        });
+                    }
+                }
+            }
+            return HttpResponse::Ok().json(inserted);
+        }
+    };
+}
+
 #[get("/play/{id}")]
 pub async fn play(
+    req: HttpRequest,
     path: Path<String>,
     data: Data<SyncSender<PlayerEvent>>,
+    config: Data<Arc<Mutex<SpotifmConfig>>>,
     session: Data<Arc<Mutex<Session>>>,
     db: Data<SpotifyDatabase>,
+    output: Data<StationOutput>,
 ) -> HttpResponse {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
     let now_playing = db.current_track().unwrap();
     let next_playing = db.next_track().unwrap();
 
@@ -336,19 +586,13 @@ pub async fn play(
             return match spotify.track(TrackId::from_id(path.0).unwrap()) {
                 Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err.to_string())])),
                 Ok(track) => {
-                    let spotify_track = SpotifyTrack::new(
-                        track
-                            .id
-                            .unwrap()
-                            .to_string()
-                            .split(":")
-                            .collect::<Vec<&str>>()
-                            .get(2)
-                            .unwrap()
-                            .to_string(),
-                        track.name,
-                        track.artists.iter().map(|x| x.clone().name).collect(),
-                    );
+                    let mut spotify_track = from_full_track(track);
+                    spotify_track.requester = query.get("requester").cloned();
+                    spotify_track.via = query.get("via").cloned();
+
+                    spotify_track.shouted_out = spotify_track.requester.is_some()
+                        && announce::announces(&config.lock().unwrap(), AnnounceEvent::Play);
+
                     return match db.queue_track(spotify_track.clone()) {
                         Err(err) => {
                             HttpResponse::Ok().json(HashMap::from([("error", err.to_string())]))
@@ -361,6 +605,11 @@ pub async fn play(
                                 Err(err) => HttpResponse::Ok()
                                     .json(HashMap::from([("error", err.to_string())])),
                                 Ok(_) => {
+                                    // rendered in the background and played once the player stopped, before the requested track
+                                    if spotify_track.shouted_out {
+                                        output.cue(announce::event(config.get_ref().clone(), AnnounceEvent::Play, spotify_track.clone()));
+                                    }
+
                                     return match data.send(PlayerEvent::Stopped {
                                         play_request_id: 0,
                                         track_id: spotify_track.spotify_id(),
@@ -379,6 +628,22 @@ pub async fn play(
     };
 }
 
+#[get("/queue/entry/{rid}")]
+pub async fn get_entry(path: Path<u64>, db: Data<SpotifyDatabase>) -> HttpResponse {
+    return match db.get_track(path.0) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(track) => HttpResponse::Ok().json(track),
+    };
+}
+
+#[delete("/queue/entry/{rid}")]
+pub async fn remove_entry(path: Path<u64>, db: Data<SpotifyDatabase>) -> HttpResponse {
+    return match db.remove_track(path.0) {
+        Err(err) => HttpResponse::Ok().json(HashMap::from([("error", err)])),
+        Ok(track) => HttpResponse::Ok().json(track),
+    };
+}
+
 #[get("/playlist")]
 pub async fn show_playlist(db: Data<SpotifyDatabase>) -> HttpResponse {
     return match db.read() {
@@ -387,67 +652,100 @@ pub async fn show_playlist(db: Data<SpotifyDatabase>) -> HttpResponse {
     };
 }
 
-async fn api(session: Data<Arc<Mutex<Session>>>) -> Result<AuthCodeSpotify, Option<String>> {
-    return match keymaster::get_token(&session.lock().unwrap(), CLIENT_ID, SCOPES).await {
-        Err(_) => Err(Some("could not get token".to_string())),
-        Ok(search_token) => {
-            let token = rspotify::Token {
-                access_token: search_token.access_token.clone(),
-                expires_in: ChronoDuration::seconds(search_token.expires_in.into()),
-                expires_at: Some(
-                    Utc::now() + ChronoDuration::seconds(search_token.expires_in.into()),
-                ),
-                refresh_token: None,
-                scopes: HashSet::from_iter(SCOPES.split(",").into_iter().map(|x| x.to_string())),
-            };
-
-            let mut spotify = rspotify::AuthCodeSpotify::from_token(token.clone());
-
-            spotify.creds.id = CLIENT_ID.to_string();
+fn from_full_track(track: FullTrack) -> SpotifyTrack {
+    let mut spotify_track = SpotifyTrack::new(
+        track
+            .id
+            .unwrap()
+            .to_string()
+            .split(":")
+            .collect::<Vec<&str>>()
+            .get(2)
+            .unwrap()
+            .to_string(),
+        track.name,
+        track.artists.iter().map(|x| x.clone().name).collect(),
+    );
+    spotify_track.isrc = track.external_ids.get("isrc").cloned();
+    spotify_track.popularity = Some(track.popularity);
+    return spotify_track;
+}
 
-            Ok(spotify)
-        }
-    };
+async fn api(session: Data<Arc<Mutex<Session>>>) -> Result<AuthCodeSpotify, Option<String>> {
+    let session = session.lock().unwrap().clone();
+    return auth::web_api(&session).await.map_err(Some);
 }
 
 #[actix_rt::main]
-pub async fn start(tx: SyncSender<PlayerEvent>, config: Arc<Mutex<SpotifmConfig>>, session: Arc<Mutex<Session>>, db: SpotifyDatabase) {
+pub async fn start(listen: String, tx: SyncSender<PlayerEvent>, config: Arc<Mutex<SpotifmConfig>>, session: Arc<Mutex<Session>>, db: SpotifyDatabase, output: StationOutput, status: StationStatus) -> Result<Server, String> {
+    let (server_tx, server_rx) = channel();
+
     thread::spawn(move || {
         //let session = session.lock().unwrap().clone();
-        match rt::System::new("rest-api").block_on(
-            HttpServer::new(move || {
-                let tx = web::Data::new(tx.clone());
-                let config = web::Data::new(config.clone());
-                let session = web::Data::new(session.clone());
-                let db = web::Data::new(db.clone());
-                App::new()
-                    .wrap(middleware::Logger::default())
-                    .app_data(tx)
-                    .app_data(config)
-                    .app_data(session)
-                    .app_data(db)
-                    .service(np)
-                    .service(prev_track)
-                    .service(next_track)
-                    .service(skip)
-                    .service(queue)
-                    .service(play)
-                    .service(search)
-                    .service(show_playlist)
-                    .service(shuffle)
-                    .service(get_announce)
-                    .service(do_espeak_say)
-                    .service(do_elevenlabs_say)
-                    .service(edit_announce_song)
-                    .service(edit_announce_bumper)
-                    .service(delete_announce_bumper_tags)
-            })
-            .bind("0.0.0.0:9090")
-            .unwrap()
-            .run(),
-        ) {
+        let mut system = rt::System::new("rest-api");
+        let server = HttpServer::new(move || {
+            let tx = web::Data::new(tx.clone());
+            let config = web::Data::new(config.clone());
+            let session = web::Data::new(session.clone());
+            let db = web::Data::new(db.clone());
+            let output = web::Data::new(output.clone());
+            let status = web::Data::new(status.clone());
+            App::new()
+                .wrap(middleware::Logger::default())
+                .app_data(tx)
+                .app_data(config)
+                .app_data(session)
+                .app_data(db)
+                .app_data(output)
+                .app_data(status)
+                .app_data(PayloadConfig::new(MAX_UPLOAD))
+                .service(np)
+                .service(get_status)
+                .service(prev_track)
+                .service(next_track)
+                .service(skip)
+                .service(queue)
+                .service(get_entry)
+                .service(remove_entry)
+                .service(queue_uris)
+                .service(play)
+                .service(search)
+                .service(show_playlist)
+                .service(shuffle)
+                .service(get_announce)
+                .service(do_espeak_say)
+                .service(do_say)
+                .service(do_elevenlabs_say)
+                .service(edit_announce_song)
+                .service(edit_announce_bumper)
+                .service(delete_announce_bumper_tags)
+                .service(get_config)
+                .service(save_config)
+                .service(list_jingles)
+                .service(upload_jingle)
+                .service(delete_jingle)
+        })
+        .bind(listen.as_str());
+
+        let server = match server {
+            Err(err) => {
+                let _ = server_tx.send(Err(format!("could not listen on {}: {}", listen, err)));
+                return;
+            }
+            Ok(server) => server.run(),
+        };
+
+        // handed back so the server can be drained on shutdown
+        server_tx.send(Ok(server.clone())).unwrap();
+
+        match system.block_on(server) {
             Ok(_) => {}
             Err(err) => panic!("{}", err.to_string()),
         };
     });
+
+    return match server_rx.recv() {
+        Err(_) => Err("rest api thread exited".to_string()),
+        Ok(server) => server,
+    };
 }
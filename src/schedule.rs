@@ -0,0 +1,67 @@
+use std::str::FromStr;
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+use crate::config::SpotifmScheduleCfg;
+
+// clock based announcements, checked at every track boundary
+pub struct Scheduler {
+    last: DateTime<Local>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        return Scheduler { last: Local::now() };
+    }
+
+    // templates of everything scheduled since the last check, an announcement that came due
+    // several times during a long track only plays once
+    pub fn due(&mut self, config: &SpotifmScheduleCfg) -> Vec<String> {
+        let now = Local::now();
+        let mut due: Vec<String> = Vec::new();
+
+        if config.station_id.enable && self.is_due("0 0 * * * *", now) {
+            match config.station_id.texts.choose(&mut thread_rng()) {
+                None => {}
+                Some(text) => due.push(text.clone()),
+            }
+        }
+
+        if config.time_check.enable && config.time_check.minutes.len() > 0 {
+            let minutes = config
+                .time_check
+                .minutes
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            if self.is_due(format!("0 {} * * * *", minutes).as_str(), now) {
+                due.push(config.time_check.template.clone());
+            }
+        }
+
+        for message in config.messages.iter() {
+            if self.is_due(message.cron.as_str(), now) {
+                due.push(message.text.clone());
+            }
+        }
+
+        self.last = now;
+        return due;
+    }
+
+    fn is_due(&self, expression: &str, now: DateTime<Local>) -> bool {
+        return match Schedule::from_str(expression) {
+            Err(err) => {
+                eprintln!("Invalid schedule \"{}\": {}", expression, err);
+                false
+            }
+            Ok(schedule) => match schedule.after(&self.last).next() {
+                None => false,
+                Some(next) => next <= now,
+            },
+        };
+    }
+}
@@ -0,0 +1,114 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::config::ShuffleMode;
+use crate::db::SpotifyTrack;
+
+// popularity assumed for tracks spotify didn't give us one for
+const DEFAULT_POPULARITY: u32 = 50;
+
+pub fn rng(seed: Option<u64>) -> StdRng {
+    return match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+}
+
+pub fn order(mode: ShuffleMode, mut tracks: Vec<SpotifyTrack>, rng: &mut StdRng) -> Vec<SpotifyTrack> {
+    match mode {
+        ShuffleMode::Uniform => tracks.shuffle(rng),
+        ShuffleMode::ArtistSpread => tracks = artist_spread(tracks, rng),
+        ShuffleMode::Popularity => tracks = popularity(tracks, rng),
+        ShuffleMode::LeastRecent => {
+            // shuffle first so tracks that were never played (or played at the same time) come out mixed
+            tracks.shuffle(rng);
+            tracks.sort_by_key(|x| x.last_played);
+        }
+    }
+    return tracks;
+}
+
+// spreads each artist's tracks evenly over the playlist, each artist starting at a random offset
+fn artist_spread(tracks: Vec<SpotifyTrack>, rng: &mut StdRng) -> Vec<SpotifyTrack> {
+    // ordered by artist so the same seed draws the same offsets
+    let mut groups: BTreeMap<String, Vec<SpotifyTrack>> = BTreeMap::new();
+
+    for track in tracks {
+        let artist = track.artists.get(0).cloned().unwrap_or_default();
+        groups.entry(artist).or_insert_with(Vec::new).push(track);
+    }
+
+    let mut placed: Vec<(f64, SpotifyTrack)> = Vec::new();
+
+    for (_, mut group) in groups {
+        group.shuffle(rng);
+        let step = 1.0 / group.len() as f64;
+        let offset = rng.gen::<f64>() * step;
+        for (idx, track) in group.into_iter().enumerate() {
+            let jitter = (rng.gen::<f64>() - 0.5) * step * 0.2;
+            placed.push((offset + idx as f64 * step + jitter, track));
+        }
+    }
+
+    placed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    return placed.into_iter().map(|x| x.1).collect();
+}
+
+// weighted random order without replacement, more popular tracks tend to come first
+fn popularity(tracks: Vec<SpotifyTrack>, rng: &mut StdRng) -> Vec<SpotifyTrack> {
+    let mut keyed: Vec<(f64, SpotifyTrack)> = tracks
+        .into_iter()
+        .map(|track| {
+            let weight = (track.popularity.unwrap_or(DEFAULT_POPULARITY) + 1) as f64;
+            (rng.gen::<f64>().powf(1.0 / weight), track)
+        })
+        .collect();
+
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    return keyed.into_iter().map(|x| x.1).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [ShuffleMode; 4] = [
+        ShuffleMode::Uniform,
+        ShuffleMode::ArtistSpread,
+        ShuffleMode::Popularity,
+        ShuffleMode::LeastRecent,
+    ];
+
+    fn tracks() -> Vec<SpotifyTrack> {
+        return (0..60)
+            .map(|i| {
+                let mut track = SpotifyTrack::new(format!("track{}", i), format!("Track {}", i), vec![format!("Artist {}", i % 7)]);
+                track.popularity = Some(i * 13 % 100);
+                track.last_played = if i % 3 == 0 { None } else { Some((i % 5) as i64) };
+                track
+            })
+            .collect();
+    }
+
+    fn ids(tracks: &[SpotifyTrack]) -> Vec<u64> {
+        return tracks.iter().map(|x| x.rid).collect();
+    }
+
+    #[test]
+    fn same_seed_same_order() {
+        let tracks = tracks();
+
+        for mode in MODES {
+            let first = order(mode, tracks.clone(), &mut rng(Some(42)));
+            let second = order(mode, tracks.clone(), &mut rng(Some(42)));
+            assert_eq!(ids(&first), ids(&second));
+
+            let mut sorted = ids(&first);
+            sorted.sort();
+            assert_eq!(sorted, ids(&tracks));
+        }
+    }
+}
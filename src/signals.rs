@@ -1,22 +1,73 @@
-use std::{thread, process::exit};
-use signal_hook::{ consts::{SIGINT,SIGALRM}, iterator::Signals};
+use std::{thread, process::exit, time::{Duration, SystemTime}};
+use signal_hook::{ consts::{SIGINT,SIGTERM,SIGALRM,SIGHUP}, iterator::Signals};
 use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{self, SpotifmConfig};
 
 pub enum SignalMessage {
     SessionExpired,
+    Reload,
+    Shutdown,
 }
 
-pub fn start(signal_tx: SyncSender<SignalMessage>) {
-    let mut signals = Signals::new(&[SIGINT,SIGALRM]).expect("error creating signal handler");
+pub fn start(signal_tx: SyncSender<SignalMessage>, config: Arc<Mutex<SpotifmConfig>>) {
+    let mut signals = Signals::new(&[SIGINT,SIGTERM,SIGALRM,SIGHUP]).expect("error creating signal handler");
 
     thread::spawn(move || {
+        let mut stopping = false;
+
         for sig in signals.forever() {
             eprintln!("Received signal {:?}", sig);
             match sig {
-                SIGINT => exit(0),
+                // a second signal doesn't wait for the shutdown to finish
+                SIGINT | SIGTERM if stopping => exit(1),
+                SIGINT | SIGTERM => {
+                    stopping = true;
+                    signal_tx.send(SignalMessage::Shutdown).unwrap();
+
+                    let timeout = config.lock().unwrap().shutdown.timeout_secs;
+                    thread::spawn(move || {
+                        thread::sleep(Duration::from_secs(timeout));
+                        eprintln!("Shutdown took longer than {}s, exiting", timeout);
+                        exit(1);
+                    });
+                }
                 SIGALRM => signal_tx.send(SignalMessage::SessionExpired).unwrap(),
+                SIGHUP => signal_tx.send(SignalMessage::Reload).unwrap(),
                 _ => {},
             }
         }
     });
-}
\ No newline at end of file
+}
+
+// asks for a reload whenever the modification time of the config file changes while `watch` is on,
+// changes made by the station's own `save` are passed over
+pub fn watch(config: Arc<Mutex<SpotifmConfig>>, signal_tx: SyncSender<SignalMessage>) {
+    thread::spawn(move || {
+        let modified = |path: &str| -> Option<SystemTime> {
+            return std::fs::metadata(path).and_then(|x| x.modified()).ok();
+        };
+
+        let mut last = modified(config.lock().unwrap().path.as_str());
+
+        loop {
+            thread::sleep(Duration::from_secs(2));
+
+            let (path, watching) = {
+                let config = config.lock().unwrap();
+                (config.path.clone(), config.watch)
+            };
+
+            let current = match modified(path.as_str()) {
+                Some(current) if Some(current) != last => current,
+                _ => continue,
+            };
+            last = Some(current);
+
+            if watching && !config::saved_at(path.as_str(), current) {
+                signal_tx.send(SignalMessage::Reload).unwrap();
+            }
+        }
+    });
+}
@@ -0,0 +1,55 @@
+use std::sync::{Arc, Mutex};
+use chrono::Utc;
+use serde::Serialize;
+
+// connection health of the station, reported by `/status`
+#[derive(Clone)]
+pub struct StationStatus {
+    info: Arc<Mutex<StatusInfo>>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct StatusInfo {
+    pub connected: bool,
+    pub reconnects: u64,
+    pub failed_reconnects: u64,
+    pub last_reconnect: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+impl StationStatus {
+    pub fn new() -> StationStatus {
+        return StationStatus {
+            info: Arc::new(Mutex::new(StatusInfo {
+                connected: true,
+                reconnects: 0,
+                failed_reconnects: 0,
+                last_reconnect: None,
+                last_error: None,
+            })),
+        };
+    }
+
+    pub fn disconnected(&self, reason: &str) {
+        let mut info = self.info.lock().unwrap();
+        info.connected = false;
+        info.last_error = Some(reason.to_string());
+    }
+
+    pub fn failed(&self, err: &str) {
+        let mut info = self.info.lock().unwrap();
+        info.failed_reconnects += 1;
+        info.last_error = Some(err.to_string());
+    }
+
+    pub fn reconnected(&self) {
+        let mut info = self.info.lock().unwrap();
+        info.connected = true;
+        info.reconnects += 1;
+        info.last_reconnect = Some(Utc::now().timestamp());
+    }
+
+    pub fn info(&self) -> StatusInfo {
+        return self.info.lock().unwrap().clone();
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+// fills in `{name}` placeholders from `vars`, unknown names become empty and `{{`/`}}` are literal braces
+pub fn render(template: &str, vars: &HashMap<String, String>) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                if closed {
+                    out.push_str(vars.get(name.trim()).map(|x| x.as_str()).unwrap_or(""));
+                } else {
+                    out.push('{');
+                    out.push_str(name.as_str());
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    // collapse the gaps left by empty fields
+    return out.split_whitespace().collect::<Vec<&str>>().join(" ");
+}
+
+// "a", "a and b", "a, b and c"
+pub fn join_names(names: &[String]) -> String {
+    return match names.len() {
+        0 => "".to_string(),
+        1 => names[0].clone(),
+        len => format!("{} and {}", names[..len - 1].join(", "), names[len - 1]),
+    };
+}
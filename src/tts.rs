@@ -0,0 +1,419 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::{
+    SpotifmConfig, SpotifmElevenLabsCfg, SpotifmEspeakCfg, SpotifmHttpTtsCfg, SpotifmPiperCfg, TtsKind,
+};
+
+static TMP_IDX: AtomicUsize = AtomicUsize::new(0);
+
+pub trait TtsEngine {
+    // renders `text` to an audio file (wav for the local engines)
+    fn synthesize(&self, text: &str) -> Result<Vec<u8>, String>;
+
+    // everything besides the text that affects the rendered audio
+    fn settings(&self) -> String;
+}
+
+// keeps rendered audio on disk, keyed by the text and voice settings
+pub struct Cached {
+    pub engine: Box<dyn TtsEngine>,
+    pub dir: PathBuf,
+    pub max_files: usize,
+}
+
+pub struct Espeak {
+    pub binary: String,
+    pub config: SpotifmEspeakCfg,
+}
+
+pub struct Piper {
+    pub config: SpotifmPiperCfg,
+}
+
+pub struct HttpTts {
+    pub config: SpotifmHttpTtsCfg,
+}
+
+pub struct ElevenLabs {
+    pub config: SpotifmElevenLabsCfg,
+}
+
+// picks the engine for an announcement, `espeak` holds that announcement's espeak settings
+pub fn engine(kind: TtsKind, espeak: &SpotifmEspeakCfg, config: &SpotifmConfig) -> Box<dyn TtsEngine> {
+    let engine: Box<dyn TtsEngine> = match kind {
+        TtsKind::Espeak => Box::new(Espeak { binary: "espeak".to_string(), config: espeak.clone() }),
+        TtsKind::EspeakNg => Box::new(Espeak { binary: "espeak-ng".to_string(), config: espeak.clone() }),
+        TtsKind::Piper => Box::new(Piper { config: config.tts.piper.clone() }),
+        TtsKind::Http => Box::new(HttpTts { config: config.tts.http.clone() }),
+        TtsKind::ElevenLabs => Box::new(ElevenLabs { config: config.elevenlabs.clone() }),
+    };
+
+    return match &config.tts.cache_dir {
+        None => engine,
+        Some(dir) => Box::new(Cached {
+            engine: engine,
+            dir: PathBuf::from(dir),
+            max_files: config.tts.cache_max_files,
+        }),
+    };
+}
+
+// same as `engine` but speaking with `voice`, which is an espeak voice, piper model, elevenlabs voice id
+// or the `voice` param of the http api depending on `kind`
+pub fn with_voice(kind: TtsKind, espeak: &SpotifmEspeakCfg, config: &SpotifmConfig, voice: &str) -> Box<dyn TtsEngine> {
+    let mut espeak = espeak.clone();
+    let mut config = config.clone();
+
+    match kind {
+        TtsKind::Espeak | TtsKind::EspeakNg => espeak.voice = voice.to_string(),
+        TtsKind::Piper => config.tts.piper.model = voice.to_string(),
+        TtsKind::Http => {
+            if config.tts.http.params.is_null() {
+                config.tts.http.params = json!({});
+            }
+            match config.tts.http.params.as_object_mut() {
+                None => eprintln!("http tts params aren't an object, ignoring voice {}", voice),
+                Some(params) => {
+                    params.insert("voice".to_string(), Value::String(voice.to_string()));
+                }
+            }
+        }
+        TtsKind::ElevenLabs => config.elevenlabs.voice = voice.to_string(),
+    }
+
+    return engine(kind, &espeak, &config);
+}
+
+pub fn tmp_path(ext: &str) -> std::path::PathBuf {
+    return std::env::temp_dir().join(format!(
+        "spotifm-{}-{}.{}",
+        std::process::id(),
+        TMP_IDX.fetch_add(1, Ordering::Relaxed),
+        ext
+    ));
+}
+
+impl TtsEngine for Cached {
+    fn synthesize(&self, text: &str) -> Result<Vec<u8>, String> {
+        // a stable digest, so the cache survives restarts and upgrades
+        let key = format!("{}\0{}", self.engine.settings(), text);
+        let path = self.dir.join(format!("{:x}", Sha256::digest(key.as_bytes())));
+
+        match std::fs::read(&path) {
+            Err(_) => {}
+            Ok(audio) => {
+                // pruning goes by mtime, so clips in use stay cached
+                match File::options().append(true).open(&path).and_then(|file| file.set_modified(SystemTime::now())) {
+                    Err(err) => eprintln!("Error touching {}: {}", path.display(), err),
+                    Ok(_) => {}
+                }
+                return Ok(audio);
+            }
+        }
+
+        let audio = self.engine.synthesize(text)?;
+
+        match std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(&path, &audio)) {
+            Err(err) => eprintln!("Error caching speech in {}: {}", self.dir.display(), err),
+            Ok(_) => self.prune(),
+        }
+
+        return Ok(audio);
+    }
+
+    fn settings(&self) -> String {
+        return self.engine.settings();
+    }
+}
+
+impl Cached {
+    // drops the oldest files once the cache is full
+    fn prune(&self) {
+        let mut files: Vec<(SystemTime, PathBuf)> = match std::fs::read_dir(&self.dir) {
+            Err(_) => return,
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+                .collect(),
+        };
+
+        if files.len() <= self.max_files {
+            return;
+        }
+
+        files.sort();
+        for (_, path) in files.iter().take(files.len() - self.max_files) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl TtsEngine for Espeak {
+    fn synthesize(&self, text: &str) -> Result<Vec<u8>, String> {
+        let output = Command::new(self.binary.as_str())
+            .arg("--stdout")
+            .arg("-s")
+            .arg(self.config.speed.to_string())
+            .arg("-a")
+            .arg(self.config.amplitude.to_string())
+            .arg("-p")
+            .arg(self.config.pitch.to_string())
+            .arg("-g")
+            .arg(self.config.gap.to_string())
+            .arg("-v")
+            .arg(self.config.voice.to_string())
+            .arg(text)
+            .output()
+            .map_err(|err| format!("could not run {}: {}", self.binary, err))?;
+
+        if !output.status.success() {
+            return Err(format!("{} failed: {}", self.binary, String::from_utf8_lossy(&output.stderr)));
+        }
+
+        return Ok(output.stdout);
+    }
+
+    fn settings(&self) -> String {
+        return format!("{}:{}", self.binary, serde_json::to_string(&self.config).unwrap());
+    }
+}
+
+impl TtsEngine for Piper {
+    fn synthesize(&self, text: &str) -> Result<Vec<u8>, String> {
+        let path = tmp_path("wav");
+
+        let mut command = Command::new(self.config.binary.as_str());
+        command
+            .arg("--model")
+            .arg(self.config.model.as_str())
+            .arg("--output_file")
+            .arg(&path)
+            .arg("--length_scale")
+            .arg(self.config.length_scale.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+
+        if let Some(speaker) = self.config.speaker {
+            command.arg("--speaker").arg(speaker.to_string());
+        }
+
+        let mut child = command.spawn().map_err(|err| format!("could not run piper: {}", err))?;
+
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(text.as_bytes())
+            .map_err(|err| format!("could not write to piper: {}", err))?;
+
+        let output = child.wait_with_output().map_err(|err| err.to_string())?;
+
+        if !output.status.success() {
+            return Err(format!("piper failed: {}", String::from_utf8_lossy(&output.stderr)));
+        }
+
+        let audio = std::fs::read(&path).map_err(|err| format!("could not read piper output: {}", err));
+        let _ = std::fs::remove_file(&path);
+        return audio;
+    }
+
+    fn settings(&self) -> String {
+        return format!("piper:{}", serde_json::to_string(&self.config).unwrap());
+    }
+}
+
+impl TtsEngine for HttpTts {
+    fn synthesize(&self, text: &str) -> Result<Vec<u8>, String> {
+        let mut body = match self.config.params.clone() {
+            Value::Object(params) => params,
+            _ => serde_json::Map::new(),
+        };
+        body.insert(self.config.text_field.clone(), Value::String(text.to_string()));
+
+        let mut request = ureq::post(self.config.url.as_str())
+            .timeout(Duration::from_secs(self.config.timeout));
+
+        for (name, value) in self.config.headers.iter() {
+            request = request.set(name.as_str(), value.as_str());
+        }
+
+        return match request.send_json(Value::Object(body)) {
+            Err(err) => Err(format!("tts request failed: {}", err)),
+            Ok(response) => {
+                let mut audio: Vec<u8> = Vec::new();
+                response
+                    .into_reader()
+                    .read_to_end(&mut audio)
+                    .map_err(|err| format!("could not read tts response: {}", err))?;
+                Ok(audio)
+            }
+        };
+    }
+
+    fn settings(&self) -> String {
+        let headers: BTreeMap<&String, &String> = self.config.headers.iter().collect();
+        return format!(
+            "http:{}:{}:{}:{}",
+            self.config.url,
+            self.config.text_field,
+            self.config.params,
+            serde_json::to_string(&headers).unwrap()
+        );
+    }
+}
+
+impl TtsEngine for ElevenLabs {
+    fn synthesize(&self, text: &str) -> Result<Vec<u8>, String> {
+        if self.config.key.is_empty() || self.config.voice.is_empty() {
+            return Err("elevenlabs key and voice are not configured".to_string());
+        }
+
+        let url = format!(
+            "{}/v1/text-to-speech/{}",
+            self.config.url.trim_end_matches('/'),
+            self.config.voice
+        );
+
+        let response = ureq::post(url.as_str())
+            .timeout(Duration::from_secs(self.config.timeout))
+            .set("accept", "audio/mpeg")
+            .set("xi-api-key", self.config.key.as_str())
+            .send_json(json!({
+                "text": text,
+                "model_id": self.config.model_id,
+                "voice_settings": {
+                    "stability": self.config.stability,
+                    "similarity_boost": self.config.similarity_boost,
+                }
+            }));
+
+        return match response {
+            Err(ureq::Error::Status(code, response)) => Err(format!(
+                "elevenlabs returned {}: {}",
+                code,
+                response.into_string().unwrap_or_default()
+            )),
+            Err(err) => Err(format!("elevenlabs request failed: {}", err)),
+            Ok(response) => {
+                let mut audio: Vec<u8> = Vec::new();
+                response
+                    .into_reader()
+                    .read_to_end(&mut audio)
+                    .map_err(|err| format!("could not read elevenlabs response: {}", err))?;
+                Ok(audio)
+            }
+        };
+    }
+
+    fn settings(&self) -> String {
+        return format!(
+            "elevenlabs:{}:{}:{}:{}",
+            self.config.voice, self.config.model_id, self.config.stability, self.config.similarity_boost
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+
+    // answers one request with `status` and `body`, sends back the request head and body
+    fn serve(status: &'static str, body: &'static str) -> (String, Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = channel();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            let mut len = 0;
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+                head.push_str(&line);
+            }
+
+            let mut request = vec![0; len];
+            reader.read_exact(&mut request).unwrap();
+
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+
+            tx.send((head, String::from_utf8(request).unwrap())).unwrap();
+        });
+
+        return (url, rx);
+    }
+
+    fn elevenlabs(url: String) -> ElevenLabs {
+        return ElevenLabs {
+            config: SpotifmElevenLabsCfg {
+                key: "secret".to_string(),
+                voice: "voice1".to_string(),
+                model_id: "eleven_multilingual_v2".to_string(),
+                stability: 0.5,
+                similarity_boost: 0.75,
+                url: url,
+                timeout: 5,
+                ..SpotifmElevenLabsCfg::default()
+            },
+        };
+    }
+
+    #[test]
+    fn elevenlabs_sends_escaped_json() {
+        let (url, rx) = serve("200 OK", "mp3 data");
+        let text = "now playing \"Song\" by\nArtist \\ Band";
+
+        let audio = elevenlabs(url).synthesize(text).unwrap();
+        assert_eq!(audio, b"mp3 data");
+
+        let (head, body) = rx.recv().unwrap();
+        assert!(head.starts_with("POST /v1/text-to-speech/voice1 "));
+        assert!(head.to_lowercase().contains("xi-api-key: secret"));
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["text"], text);
+        assert_eq!(body["model_id"], "eleven_multilingual_v2");
+        assert_eq!(body["voice_settings"]["stability"], 0.5);
+        assert_eq!(body["voice_settings"]["similarity_boost"], 0.75);
+    }
+
+    #[test]
+    fn elevenlabs_reports_error_status() {
+        let (url, _rx) = serve("401 Unauthorized", "invalid api key");
+
+        let err = elevenlabs(url).synthesize("hello").unwrap_err();
+        assert_eq!(err, "elevenlabs returned 401: invalid api key");
+    }
+}